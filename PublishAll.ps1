@@ -68,7 +68,7 @@ Remove-Item "Publish/ToUpload/utoc-extractor" -Recurse -ErrorAction SilentlyCont
 New-Item "Publish/ToUpload/utoc-extractor" -ItemType Directory -ErrorAction SilentlyContinue
 New-Item "Publish/ToUpload/utoc-extractor/egui.ini" -ErrorAction SilentlyContinue
 
-Copy-Item "UtocEmulator/utoc-extractor/data/config.ini" -Destination "Publish/ToUpload/utoc-extractor"
+Copy-Item "UtocEmulator/utoc-extractor/data/keys.ini" -Destination "Publish/ToUpload/utoc-extractor"
 Copy-Item "UtocEmulator/target/$target_triple/release/utoc-extractor.exe" -Destination "Publish/ToUpload/utoc-extractor"
 
 Compress-Archive -Path "Publish/ToUpload/utoc-extractor/*" -Destination "Publish/ToUpload/utoc-extractor/utoc-extractor.zip"
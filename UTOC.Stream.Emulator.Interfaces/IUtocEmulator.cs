@@ -6,4 +6,31 @@ public interface IUtocEmulator
     public void AddFromFolder(string folder);
 
     public void AddFromFolderWithMount(string folder, string virtualfolder);
+
+    /// <summary>
+    /// Adds an asset from memory instead of from a file on disk. The path is in the same form as a file inside
+    /// of an UnrealEssentials folder (e.g. P3R/Content/Characters/Player.uasset). The data is copied.
+    /// </summary>
+    public void AddFromMemory(string assetPath, byte[] data);
+
+    /// <summary>
+    /// Checks if a package or asset is emulated. Takes a package name (/Game/...), a package id in hex (0x...)
+    /// or a path relative to the mount point (e.g. P3R/Content/Characters/Player.uasset).
+    /// </summary>
+    public bool IsEmulated(string path);
+
+    /// <summary>
+    /// Checks if a package is emulated by it's package id.
+    /// </summary>
+    public bool IsEmulated(ulong packageId);
+
+    /// <summary>
+    /// Gets every asset that is being emulated, sorted by asset path.
+    /// </summary>
+    public EmulatedAsset[] GetEmulatedAssets();
+
+    /// <summary>
+    /// Computes the store entry that an emulated package is given. Returns null if the package isn't emulated.
+    /// </summary>
+    public StoreEntryInfo? GetStoreEntry(ulong packageId);
 }
@@ -37,7 +37,7 @@ public enum EngineVersion : uint
     UE_4_24 = (4 << 0x8) + 24,
     // UTOC Versions
     UE_4_25 = (4 << 0x8) + 25,
-    UE_4_26 = (4 << 0x8) + 26, // 4.25+ (e.g Scarlet Nexus) is treated as 4.26
+    UE_4_26 = (4 << 0x8) + 26,
     UE_4_27 = (4 << 0x8) + 27,
     UE_5_0 = (5 << 0x8) + 0,
     UE_5_1 = (5 << 0x8) + 1,
@@ -47,5 +47,22 @@ public enum EngineVersion : uint
     UE_5_5 = (5 << 0x8) + 5,
     UE_5_6 = (5 << 0x8) + 6,
     UE_5_7 = (5 << 0x8) + 7,
-    // if there are any games that require a special ID, then define them with [value] << 0x10
-}
\ No newline at end of file
+    // Games that require a special ID are defined as [value] | [profile] << 0x10. These must match
+    // GAME_PROFILES in utoc-lib, which holds the version overrides for each game
+    UE_4_27_P3R = UE_4_27 | 1 << 0x10,
+    UE_4_25_ScarletNexus = UE_4_25 | 1 << 0x10,
+}
+
+/// <summary>
+/// An asset that UTOC Emulator is adding or replacing.
+/// </summary>
+/// <param name="AssetPath">Path relative to the mount point, e.g. ../../../Game/Characters/Player.uasset</param>
+/// <param name="SourceFolder">The folder the asset was added from, or null if it was added from memory</param>
+/// <param name="PackageId">The package id for the asset's path</param>
+/// <param name="Size">Size of the asset in bytes</param>
+public record EmulatedAsset(string AssetPath, string? SourceFolder, ulong PackageId, ulong Size);
+
+/// <summary>
+/// The store entry that an emulated package is given in the container header.
+/// </summary>
+public record StoreEntryInfo(ulong ExportBundlesSize, int ExportCount, int ExportBundleCount, ulong[] ImportedPackages);
@@ -113,6 +113,13 @@ public class SignaturePropertyFactory
         return Enum.TryParse<TocType>(str, out var Value) ? Value : throw new Exception($"Value \"{str}\" is not in TocType");
     }
     
+    private static EngineVersion HandleGameProfile(YamlNode value)
+    {
+        var str = value.Cast<YamlScalarNode>()?.Value ?? throw new Exception("Value for GameProfile must be a string");
+        return Enum.TryParse<EngineVersion>(str, out var Value) && ((uint)Value >> 0x10) != 0
+            ? Value : throw new Exception($"Value \"{str}\" is not a game profile in EngineVersion");
+    }
+    
     private static StartLoadingDelegateType HandleStartLoadDelegate(YamlNode value)
     {
         var str = value.Cast<YamlScalarNode>()?.Value ?? throw new Exception("Value for StartLoadDelegate must be a string");
@@ -254,6 +261,11 @@ public class SignaturePropertyFactory
                 case "ProductName":
                     ProductName ??= HandleScalar("ProductName", child.Value);
                     break;
+                case "GameProfile":
+                    if (properties == null)
+                        throw new Exception("EngineVersion must be declared before setting a game profile!");
+                    properties.EngineVersion = HandleGameProfile(child.Value);
+                    break;
                 case "Signatures":
                     if (properties == null)
                         throw new Exception("EngineVersion must be declared before defining signature overrides!");
@@ -7,18 +7,33 @@ public class Api : IUtocEmulator
     private InitialiseDelegate _initialise;
     private Action<string> _addFromFolder;
     private Action<string, string> _addFromFolderWithMount;
+    private Action<string, byte[]> _addFromMemory;
+    private Func<UtocEmulator> _getEmulator;
 
-    internal Api(InitialiseDelegate initialise, Action<string> addFromFolder, Action<string, string> addFromFolderWithMount)
+    internal Api(InitialiseDelegate initialise, Action<string> addFromFolder, Action<string, string> addFromFolderWithMount,
+        Action<string, byte[]> addFromMemory, Func<UtocEmulator> getEmulator)
     {
         _initialise = initialise;
         _addFromFolder = addFromFolder;
         _addFromFolderWithMount = addFromFolderWithMount;
+        _addFromMemory = addFromMemory;
+        _getEmulator = getEmulator;
     }
 
     public void AddFromFolder(string folder) => _addFromFolder(folder);
 
     public void AddFromFolderWithMount(string folder, string virtualPath) => _addFromFolderWithMount(folder, virtualPath);
 
+    public void AddFromMemory(string assetPath, byte[] data) => _addFromMemory(assetPath, data);
+
+    public bool IsEmulated(string path) => _getEmulator().IsEmulated(path);
+
+    public bool IsEmulated(ulong packageId) => _getEmulator().IsEmulated(packageId);
+
+    public EmulatedAsset[] GetEmulatedAssets() => _getEmulator().GetEmulatedAssets();
+
+    public StoreEntryInfo? GetStoreEntry(ulong packageId) => _getEmulator().GetStoreEntry(packageId);
+
     public void Initialise(EngineVersion engineVersion, bool hasUtocs, Action<string> addPakFolder, Action<string> removePakFolder)
     {
         _initialise(engineVersion, hasUtocs, addPakFolder, removePakFolder);
@@ -16,6 +16,16 @@ namespace UTOC.Stream.Emulator.Configuration
         [Description("Creates a dump of emulated IO Store files (.utoc + .ucas) as they are written.")]
         [DefaultValue(false)]
         public bool DumpFiles { get; set; } = false;
+
+        [DisplayName("Write Build Report")]
+        [Description("Writes a JSON report of every emulated asset, along with any warnings, to BuildReport.json in this mod's folder.\nAttach this file when reporting an issue with UTOC Emulator.")]
+        [DefaultValue(false)]
+        public bool WriteBuildReport { get; set; } = false;
+
+        [DisplayName("Guess Imports Without Metadata (UE 5.0 - 5.2)")]
+        [Description("Allows mods for UE 5.0 - 5.2 games that don't include asset metadata to load by guessing each asset's imports from it's name map.\nThis can be wrong for assets that import more than one package, which are listed as warnings in the log and build report.")]
+        [DefaultValue(false)]
+        public bool HeuristicImports { get; set; } = false;
     }
 
     /// <summary>
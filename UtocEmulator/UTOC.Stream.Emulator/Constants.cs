@@ -17,5 +17,6 @@ namespace UTOC.Stream.Emulator
         public static readonly string UnrealEssentialsName = "UnrealEssentials";
         public static readonly string TargetDir = "TargetFiles";
         public static readonly string DummyPakDir = "DummyPaks";
+        public static readonly string BuildReportFile = "BuildReport.json";
     }
 }
@@ -34,4 +34,6 @@ public static class LogAdapter
             RustApi.set_reloaded_logger(&ReloadedLoggerWrite);
         }
     }
+
+    public static void SetLogLevel(LogSeverity level) => RustApi.set_log_level((uint)level);
 }
\ No newline at end of file
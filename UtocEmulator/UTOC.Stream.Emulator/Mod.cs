@@ -2,6 +2,7 @@
 using FileEmulationFramework.Lib.Utilities;
 using Reloaded.Mod.Interfaces;
 using Reloaded.Mod.Interfaces.Internal;
+using System.Runtime.InteropServices;
 using IReloadedHooks = Reloaded.Hooks.ReloadedII.Interfaces.IReloadedHooks;
 using UTOC.Stream.Emulator.Configuration;
 using UTOC.Stream.Emulator.Template;
@@ -38,10 +39,21 @@ namespace UTOC.Stream.Emulator
 
             _log = new Logger(_logger, _configuration.LogLevel);
             LogAdapter.RegisterLogger(_log);
+            LogAdapter.SetLogLevel(_configuration.LogLevel);
+            if (RustApi.CheckAbi() is { } abiError)
+            {
+                _log.Fatal($"[UtocEmulator] {abiError}");
+                return;
+            }
             RustApi.SetCallbacks();
+            RustApi.set_heuristic_imports(_configuration.HeuristicImports);
+            if (_configuration.WriteBuildReport)
+                RustApi.set_build_report(Marshal.StringToHGlobalUni(
+                    Path.Combine(_modLoader.GetDirectoryForModId(_modConfig.ModId), Constants.BuildReportFile)));
 
             // Expose API
-            _api = new Api(Initialise, (folder) => _emu.AddFromFolder(folder), (folder, mount) => _emu.AddFromFolderWithMount(folder, mount));
+            _api = new Api(Initialise, (folder) => _emu.AddFromFolder(folder), (folder, mount) => _emu.AddFromFolderWithMount(folder, mount),
+                (path, data) => _emu.AddFromMemory(path, data), () => _emu);
             _modLoader.AddOrReplaceController(context.Owner, _api);
         }
 
@@ -75,6 +87,8 @@ namespace UTOC.Stream.Emulator
             // Apply settings from configuration.
             // ... your code here.
             _configuration = configuration;
+            LogAdapter.SetLogLevel(_configuration.LogLevel);
+            RustApi.set_heuristic_imports(_configuration.HeuristicImports);
             _logger.WriteLine($"[{_modConfig.ModId}] Config Updated: Applying");
         }
         #endregion
@@ -12,22 +12,141 @@ namespace UTOC.Stream.Emulator
         public nint Len;
     }
 
+    /// <summary>
+    /// Must be kept in sync with FfiStatus in ffi.rs. Values are stable and never renumbered.
+    /// </summary>
+    public enum FfiStatus : uint
+    {
+        Ok = 0,
+        InvalidArgument = 1,
+        Io = 2,
+        Error = 3,
+        Panic = 4,
+        NotFound = 5,
+    }
+
+    /// <summary>
+    /// Must be kept in sync with EmulatedAssetInfo in ffi.rs
+    /// </summary>
+    [StructLayout(LayoutKind.Sequential)]
+    public struct EmulatedAssetInfo
+    {
+        public nint AssetPath; // UTF-8
+        public nint SourceFolder; // UTF-8, 0 for in-memory assets
+        public ulong PackageId;
+        public ulong Size;
+    }
+
+    /// <summary>
+    /// Must be kept in sync with StoreEntryInfo in ffi.rs
+    /// </summary>
+    [StructLayout(LayoutKind.Sequential)]
+    public struct StoreEntryFfi
+    {
+        public ulong ExportBundlesSize;
+        public int ExportCount;
+        public int ExportBundleCount;
+        public Array<ulong> ImportedPackages;
+    }
+
     public static unsafe class RustApi
     {
         const string __DllName = "utoc_emulator";
+
+        /// <summary>
+        /// The value of ABI_VERSION in ffi.rs that these declarations were written for.
+        /// </summary>
+        public const uint AbiVersion = 2;
+
+        [DllImport(__DllName, EntryPoint = "utoc_emulator_abi_version", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        private static extern uint utoc_emulator_abi_version();
+
+        /// <summary>
+        /// Check that utoc_emulator.dll matches these declarations. The struct sizes are also pinned by the
+        /// ffi_struct_layouts test in utoc-emulator.
+        /// </summary>
+        public static string? CheckAbi()
+        {
+            var actual = utoc_emulator_abi_version();
+            if (actual != AbiVersion)
+                return $"utoc_emulator has ABI version {actual}, but version {AbiVersion} was expected. Reinstall UTOC Emulator.";
+            if (sizeof(Array<byte>) != 16 || sizeof(PartitionBlock) != 32 || sizeof(EmulatedAssetInfo) != 32 || sizeof(StoreEntryFfi) != 32)
+                return "FFI struct layouts don't match utoc_emulator";
+            return null;
+        }
         
         [DllImport(__DllName, EntryPoint = "add_from_folders", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
-        public static extern void add_from_folders(nint mod_path, EngineVersion version);
+        public static extern FfiStatus add_from_folders(nint mod_path, EngineVersion version, nint* error);
 
         [DllImport(__DllName, EntryPoint = "add_from_folders_with_mount", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
-        public static extern void add_from_folders_with_mount(nint mod_path, nint virtual_path, EngineVersion version);
+        public static extern FfiStatus add_from_folders_with_mount(nint mod_path, nint virtual_path, EngineVersion version, nint* error);
+
+        [DllImport(__DllName, EntryPoint = "add_from_memory", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern FfiStatus add_from_memory(nint asset_path, byte* data, nuint len, nint* error);
 
         [DllImport(__DllName, EntryPoint = "build_toc", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
-        public static extern bool build_toc(EngineVersion version, Array<byte>* toc, Array<PartitionBlock>* blocks, Array<byte>* header);
+        public static extern FfiStatus build_toc(EngineVersion version, Array<byte>* toc, Array<PartitionBlock>* blocks, Array<byte>* header, nint* error);
         
+        [DllImport(__DllName, EntryPoint = "free_array_u8", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern void free_array_u8(Array<byte>* array);
+        
+        [DllImport(__DllName, EntryPoint = "free_partition_blocks", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern void free_partition_blocks(Array<PartitionBlock>* array);
+        
+        [DllImport(__DllName, EntryPoint = "is_package_emulated", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern bool is_package_emulated(ulong package_id);
+        
+        [DllImport(__DllName, EntryPoint = "is_path_emulated", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern bool is_path_emulated(nint path);
+        
+        [DllImport(__DllName, EntryPoint = "get_emulated_assets", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern FfiStatus get_emulated_assets(Array<EmulatedAssetInfo>* assets, nint* error);
+        
+        [DllImport(__DllName, EntryPoint = "free_emulated_assets", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern void free_emulated_assets(Array<EmulatedAssetInfo>* assets);
+        
+        [DllImport(__DllName, EntryPoint = "get_store_entry", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern FfiStatus get_store_entry(ulong package_id, EngineVersion version, StoreEntryFfi* entry, nint* error);
+        
+        [DllImport(__DllName, EntryPoint = "free_store_entry_info", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern void free_store_entry_info(StoreEntryFfi* entry);
+        
+        [DllImport(__DllName, EntryPoint = "free_error_message", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        private static extern void free_error_message(nint message);
+
+        /// <summary>
+        /// Copy an error message returned by an FFI function into a managed string and free the original.
+        /// </summary>
+        public static string? TakeErrorMessage(nint message)
+        {
+            if (message == 0) return null;
+            var text = Marshal.PtrToStringUTF8(message);
+            free_error_message(message);
+            return text;
+        }
+        
+        [DllImport(__DllName, EntryPoint = "set_build_report", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        public static extern void set_build_report(nint report_path);
+
+        [DllImport(__DllName, EntryPoint = "set_heuristic_imports", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        internal static extern void set_heuristic_imports(bool enabled);
+
         [DllImport(__DllName, EntryPoint = "set_reloaded_logger", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
         internal static extern void set_reloaded_logger(delegate* unmanaged[Stdcall]<nint, nint, int, void> offset);
         
+        [DllImport(__DllName, EntryPoint = "clear_reloaded_logger", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        internal static extern void clear_reloaded_logger();
+        
+        [DllImport(__DllName, EntryPoint = "set_log_level", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        internal static extern void set_log_level(uint level);
+        
+        /// <summary>
+        /// Write logs that are emitted while no logger is registered to a file instead of stderr. The path is
+        /// freed by Rust, so SetCallbacks must have been called first. Pass 0 to go back to stderr.
+        /// </summary>
+        [DllImport(__DllName, EntryPoint = "set_fallback_log_file", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
+        internal static extern bool set_fallback_log_file(nint path);
+        
         [DllImport(__DllName, EntryPoint = "set_free_csharp_string", CallingConvention = CallingConvention.StdCall, ExactSpelling = true)]
         private static extern nuint set_free_csharp_string(delegate* unmanaged[Stdcall]<nint, void> offset);
         
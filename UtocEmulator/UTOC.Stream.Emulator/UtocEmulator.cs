@@ -13,12 +13,13 @@ using Strim = System.IO.Stream;
 namespace UTOC.Stream.Emulator
 {
 
-    // Must be kept in sync with PartitionBlock in toc_factory.rs
+    // Must be kept in sync with PartitionBlock in ffi.rs
     public struct PartitionBlock
     {
         public IntPtr osPath; // *const u8
         public long start; // u64
         public long length; // u64
+        public IntPtr data; // *const u8, set instead of osPath for assets added from memory
     }
     public class UtocEmulator : IEmulator
     {
@@ -35,6 +36,9 @@ namespace UTOC.Stream.Emulator
         private string ModDummyPakFilesDirectory { get; init; }
         public Action<string> AddPakFolderCb { get; set; }
 
+        // TOC, partition blocks and container header from build_toc, owned by us until they're freed
+        private unsafe Array<byte>* _buildOutput = null;
+
         private readonly ConcurrentDictionary<string, Strim?> _pathToStream = new(StringComparer.OrdinalIgnoreCase);
 
         public UtocEmulator(Logger logger, Config configuration, string modPath, Action<string> addPakFolderCb) 
@@ -77,15 +81,19 @@ namespace UTOC.Stream.Emulator
             return true;
         }
 
-        public List<StreamOffsetPair<Strim>> CreateContainerStream(nint blockPtr, int blockCount, nint headerPtr, int headerSize)
+        public unsafe List<StreamOffsetPair<Strim>> CreateContainerStream(nint blockPtr, int blockCount, nint headerPtr, int headerSize)
         {
             var streams = new List<StreamOffsetPair<Strim>>();
             long streamEnd = 0;
             for (var i = 0; i < blockCount; i++)
             {
                 var containerBlock = Marshal.PtrToStructure<PartitionBlock>(blockPtr);
+                // In-memory assets are read in place, since each block keeps its data alive until FreeBuildOutput
+                Strim blockStream = containerBlock.data != 0
+                    ? new UnmanagedMemoryStream((byte*)containerBlock.data, containerBlock.length)
+                    : new FileStream(Marshal.PtrToStringUni(containerBlock.osPath)!, FileMode.Open);
                 streams.Add(new(
-                    new FileStream(Marshal.PtrToStringUni(containerBlock.osPath), FileMode.Open),
+                    blockStream,
                     OffsetRange.FromStartAndLength(containerBlock.start, containerBlock.length)
                 ));
                 var containerBlockEnd = containerBlock.start + containerBlock.length;
@@ -182,10 +190,19 @@ namespace UTOC.Stream.Emulator
             _logger.LogInfo($"Written To {filePath}");
         }
 
-        private void AddFromFolderInner(string mod_path)
+        private unsafe void AddFromFolderInner(string mod_path)
         {
             var mod_path_unicode = Marshal.StringToHGlobalUni(mod_path);
-            RustApi.add_from_folders(mod_path_unicode, EngineVersion);
+            nint error = 0;
+            var status = RustApi.add_from_folders(mod_path_unicode, EngineVersion, &error);
+            LogFfiError(status, error);
+        }
+
+        private void LogFfiError(FfiStatus status, nint error)
+        {
+            var message = RustApi.TakeErrorMessage(error);
+            if (status != FfiStatus.Ok)
+                _logger.LogError($"[{status}] {message}");
         }
 
         public void OnModLoading(string dir_path)
@@ -194,11 +211,68 @@ namespace UTOC.Stream.Emulator
         public void AddFromFolder(string dir_path)
             => AddFromFolderInner(dir_path);
 
-        public void AddFromFolderWithMount(string dir_path, string virtual_path)
+        public unsafe void AddFromMemory(string asset_path, byte[] data)
+        {
+            var asset_path_unicode = Marshal.StringToHGlobalUni(asset_path);
+            nint error = 0;
+            FfiStatus status;
+            fixed (byte* p = data)
+                status = RustApi.add_from_memory(asset_path_unicode, p, (nuint)data.Length, &error);
+            LogFfiError(status, error);
+        }
+
+        public bool IsEmulated(string path) => RustApi.is_path_emulated(Marshal.StringToHGlobalUni(path));
+
+        public bool IsEmulated(ulong packageId) => RustApi.is_package_emulated(packageId);
+
+        public unsafe EmulatedAsset[] GetEmulatedAssets()
+        {
+            Array<EmulatedAssetInfo> assets = default;
+            nint error = 0;
+            var status = RustApi.get_emulated_assets(&assets, &error);
+            if (status != FfiStatus.Ok)
+            {
+                LogFfiError(status, error);
+                return [];
+            }
+            var result = new EmulatedAsset[assets.Len];
+            for (var i = 0; i < assets.Len; i++)
+            {
+                var asset = assets.Entries[i];
+                result[i] = new(Marshal.PtrToStringUTF8(asset.AssetPath)!, Marshal.PtrToStringUTF8(asset.SourceFolder),
+                    asset.PackageId, asset.Size);
+            }
+            RustApi.free_emulated_assets(&assets);
+            return result;
+        }
+
+        public unsafe StoreEntryInfo? GetStoreEntry(ulong packageId)
+        {
+            StoreEntryFfi entry = default;
+            nint error = 0;
+            var status = RustApi.get_store_entry(packageId, EngineVersion, &entry, &error);
+            if (status == FfiStatus.NotFound)
+            {
+                RustApi.TakeErrorMessage(error);
+                return null;
+            }
+            if (status != FfiStatus.Ok)
+            {
+                LogFfiError(status, error);
+                return null;
+            }
+            var imports = new ReadOnlySpan<ulong>(entry.ImportedPackages.Entries, (int)entry.ImportedPackages.Len).ToArray();
+            RustApi.free_store_entry_info(&entry);
+            return new(entry.ExportBundlesSize, entry.ExportCount, entry.ExportBundleCount, imports);
+        }
+
+        public unsafe void AddFromFolderWithMount(string dir_path, string virtual_path)
         {
             var mod_path_unicode = Marshal.StringToHGlobalUni(dir_path);
             var virtual_path_unicode = Marshal.StringToHGlobalUni(virtual_path);
-            RustApi.add_from_folders_with_mount(mod_path_unicode, virtual_path_unicode, EngineVersion);
+            nint error = 0;
+            var status = RustApi.add_from_folders_with_mount(mod_path_unicode, virtual_path_unicode, EngineVersion, &error);
+            LogFfiError(status, error);
         }
 
         public void MakeFilesOnInit() // from base Unreal Essentials path
@@ -208,29 +282,53 @@ namespace UTOC.Stream.Emulator
                 _logger.LogInfo("Game is not using IO Store, stopping here");
                 return;
             }
+            FreeBuildOutput();
             unsafe
             {
                 var toc = (Array<byte>*)NativeMemory.AlignedAlloc((nuint)(3 * sizeof(Array<byte>)), (nuint)sizeof(nint));
+                NativeMemory.Clear(toc, (nuint)(3 * sizeof(Array<byte>)));
+                _buildOutput = toc;
                 var blocks = (Array<PartitionBlock>*)(toc + 1);
                 var header = toc + 2;
-                var result = RustApi.build_toc( EngineVersion, toc, blocks, header);
-                if (!result)
+                nint error = 0;
+                var status = RustApi.build_toc(EngineVersion, toc, blocks, header, &error);
+                if (status != FfiStatus.Ok)
                 {
-                    _logger.LogError("An error occurred while making IO Store data");
+                    LogFfiError(status, error);
+                    FreeBuildOutput();
                     return;
                 }
+                RustApi.TakeErrorMessage(error);
 
                 if (blocks->Len == 0)
                 {
                     _logger.LogInfo("No IO store files found, not creating emulated file.");
+                    FreeBuildOutput();
                     return;   
                 }
                 TocStream = new UnmanagedMemoryStream(toc->Entries, toc->Len);
                 CasStream = new MultiStream(CreateContainerStream((nint)blocks->Entries, (int)blocks->Len,
-                    (nint)header->Entries, (int)header->Len));   
+                    (nint)header->Entries, (int)header->Len));
             }
             AddPakFolderCb(ModTargetFilesDirectory);
         }
+
+        /// <summary>
+        /// Free the TOC and container header from the last build, along with the streams that read from them.
+        /// </summary>
+        private unsafe void FreeBuildOutput()
+        {
+            if (_buildOutput == null) return;
+            TocStream?.Dispose();
+            CasStream?.Dispose();
+            TocStream = null;
+            CasStream = null;
+            RustApi.free_array_u8(_buildOutput);
+            RustApi.free_partition_blocks((Array<PartitionBlock>*)(_buildOutput + 1));
+            RustApi.free_array_u8(_buildOutput + 2);
+            NativeMemory.AlignedFree(_buildOutput);
+            _buildOutput = null;
+        }
         public void OnLoaderInit() => MakeFilesOnInit();
     }
 }
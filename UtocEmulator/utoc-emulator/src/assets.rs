@@ -1,28 +1,139 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::{Mutex, MutexGuard};
-use retoc::version::EngineVersion;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use anyhow::anyhow;
+use retoc::{FIoChunkId, FPackageId};
 use walkdir::{DirEntry, WalkDir};
 use utoc_lib::assets::*;
+use utoc_lib::chunks::{from_raw_chunk_file_name, RAW_CHUNK_FOLDER, RAW_CHUNK_MANIFEST};
 use utoc_lib::store::os_file_size;
 use crate::GenericResult;
+use crate::log;
+use utoc_lib::metadata::{MergePolicy, UtocMetadata};
+use utoc_lib::profiles::GameProfile;
+use utoc_lib::text::parse_package;
 use crate::metadata::MetadataState;
+use crate::report::{BuildReport, SourceFolderReport};
 
 type AssetListMap = HashMap<String, AssetEntry>;
 pub static ASSET_LIST: Mutex<Option<AssetListMap>> = Mutex::new(None);
 
+type RawChunkMap = HashMap<FIoChunkId, AssetEntry>;
+/// Chunks from a mod's _chunks folder. These don't have a path, so they're keyed by chunk id instead
+pub static RAW_CHUNK_LIST: Mutex<Option<RawChunkMap>> = Mutex::new(None);
+
+/// Lookups over the asset list for the query API. Kept in sync by AssetCollection::insert
+#[derive(Debug, Default)]
+pub struct AssetIndex {
+    /// Package id -> asset path of the package's .uasset or .umap
+    packages: HashMap<FPackageId, String>,
+    /// Asset path -> the folder that the asset was added from. In-memory assets don't have one
+    sources: HashMap<String, PathBuf>
+}
+
+pub static ASSET_INDEX: Mutex<Option<AssetIndex>> = Mutex::new(None);
+
+/// An asset that's being emulated, as returned from AssetCollection::emulated_assets
+#[derive(Debug, Clone)]
+pub struct EmulatedAsset {
+    pub asset_path: String,
+    pub source_folder: Option<PathBuf>,
+    pub package_id: FPackageId,
+    pub size: u64
+}
+
 #[derive(Debug)]
 pub struct AssetCollection;
 
 impl AssetCollection {
     pub(crate) fn instance() -> MutexGuard<'static, Option<AssetListMap>> {
-        let mut guard = ASSET_LIST.lock().unwrap();
+        let mut guard = ASSET_LIST.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.is_none() {
+            *guard = Some(HashMap::new());
+        }
+        guard
+    }
+
+    pub(crate) fn raw_chunks() -> MutexGuard<'static, Option<RawChunkMap>> {
+        let mut guard = RAW_CHUNK_LIST.lock().unwrap_or_else(|e| e.into_inner());
         if guard.is_none() {
             *guard = Some(HashMap::new());
         }
         guard
     }
 
+    fn index() -> MutexGuard<'static, Option<AssetIndex>> {
+        let mut guard = ASSET_INDEX.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.is_none() {
+            *guard = Some(AssetIndex::default());
+        }
+        guard
+    }
+
+    fn is_package(asset_path: &str) -> bool {
+        asset_path.rsplit_once('.').map_or(false, |(_, ext)| ext == UASSET_EXTENSION || ext == UMAP_EXTENSION)
+    }
+
+    /// Add or replace an asset. The asset list is always locked before the index
+    pub(crate) fn insert(asset_path: String, entry: AssetEntry, source: Option<&Path>) {
+        let mut assets = Self::instance();
+        let mut index = Self::index();
+        let index = index.as_mut().unwrap();
+        if Self::is_package(&asset_path) {
+            index.packages.insert(asset_path_to_package_id(&asset_path), asset_path.clone());
+        }
+        match source {
+            Some(source) => index.sources.insert(asset_path.clone(), source.to_owned()),
+            None => index.sources.remove(&asset_path)
+        };
+        assets.as_mut().unwrap().insert(asset_path, entry);
+    }
+
+    /// The asset path of an emulated package's .uasset or .umap
+    pub fn get_package_asset(package_id: FPackageId) -> Option<String> {
+        Self::index().as_ref().unwrap().packages.get(&package_id).cloned()
+    }
+
+    pub fn is_package_emulated(package_id: FPackageId) -> bool {
+        Self::get_package_asset(package_id).is_some()
+    }
+
+    /// Check a package name (/Game/...), package id (0x...) or a path relative to the mount point
+    /// (e.g P3R/Content/Characters/Player.uasset). Paths can also be for bulk data
+    pub fn is_path_emulated(path: &str) -> bool {
+        let path = path.trim().replace("\\", "/");
+        if path.starts_with('/') || path.starts_with("0x") {
+            return parse_package(&path).map_or(false, Self::is_package_emulated);
+        }
+        let path = path.trim_start_matches(MOUNT_POINT);
+        if path.splitn(3, '/').count() < 3 {
+            return false;
+        }
+        Self::instance().as_ref().unwrap().contains_key(&get_asset_path(path, None))
+    }
+
+    pub fn emulated_assets() -> Vec<EmulatedAsset> {
+        let assets = Self::instance();
+        let index = Self::index();
+        let index = index.as_ref().unwrap();
+        let mut emulated: Vec<_> = assets.as_ref().unwrap().iter().map(|(asset_path, entry)| EmulatedAsset {
+            asset_path: asset_path.clone(),
+            source_folder: index.sources.get(asset_path).cloned(),
+            package_id: asset_path_to_package_id(asset_path),
+            size: entry.size
+        }).collect();
+        emulated.sort_by(|a, b| a.asset_path.cmp(&b.asset_path));
+        emulated
+    }
+
+    // Raw chunks can have any extension, so they're identified by being in the _chunks folder
+    fn filter_raw_chunk(d: &DirEntry) -> bool {
+        d.depth() == 2 && d.path().parent().and_then(|p| p.file_name()).map_or(
+            false, |v| v.to_str().unwrap() == RAW_CHUNK_FOLDER)
+            && d.file_name().to_str().unwrap() != RAW_CHUNK_MANIFEST
+    }
+
     // Essentials 1.x: utocmeta is treated as a filename only
     fn filter_utocmeta(d: &DirEntry) -> bool {
         d.depth() == 1 && d.path().file_name().map_or(
@@ -37,7 +148,7 @@ impl AssetCollection {
                 // check the file format!
                 let check_ext = d.path().extension().map_or(
                     false, |ext| ASSET_EXTENSIONS.contains(&ext.to_str().unwrap()))
-                    || Self::filter_utocmeta(&d);
+                    || Self::filter_utocmeta(&d) || Self::filter_raw_chunk(&d);
                 if !is_file || !check_ext { return None }
                 Some(d)
             }
@@ -47,43 +158,158 @@ impl AssetCollection {
     /// Recursively registers all the assets inside of a folder into the asset list to get replaced.
     /// If you are working with an asset type that can be partially written to such as a data table,
     /// use UE Toolkit (https://github.com/RyoTune/UE.Toolkit) as it allows for file merging
-    pub(crate) fn add_from_folder<P: AsRef<Path>>(path: P, version: EngineVersion) -> GenericResult<()> {
+    pub(crate) fn add_from_folder<P: AsRef<Path>>(path: P, profile: &GameProfile) -> GenericResult<()> {
         let path = path.as_ref().to_owned();
         if !path.exists() { return Ok(()); }
-        Self::add_from_folder_inner(path, None, version)
+        Self::add_from_folder_inner(path, None, profile)
     }
 
     pub(crate) fn add_from_folder_inner(path: PathBuf, mount: Option<PathBuf>,
-        version: EngineVersion) -> GenericResult<()> {
+        profile: &GameProfile) -> GenericResult<()> {
+        let start = Instant::now();
+        BuildReport::with(|r| r.source_folders.push(SourceFolderReport {
+            path: path.to_str().unwrap().to_owned(),
+            mount: mount.as_ref().map(|m| m.to_str().unwrap().to_owned())
+        }));
+        // Metadata is collected per folder so that it can be re-keyed for the mount before it's added
+        let mut folder_meta = UtocMetadata::default();
+        // Package ids without the mount -> package ids with the mount
+        let mut mounted_ids = HashMap::new();
         for file in WalkDir::new(&path).into_iter().filter_map(Self::filter_dir_entries) {
             let os_path = file.path().to_owned();
+            if Self::filter_raw_chunk(&file) {
+                Self::add_raw_chunk(&file)?;
+                continue;
+            }
             match os_path.extension().map(|s| s.to_str().unwrap()) {
                 Some(UASSETMETA_EXTENSION) => {
                     let asset_path = convert_to_asset_path(&os_path, path.as_path(), mount.as_ref());
-                    MetadataState::instance().as_mut().unwrap().add_from_uassetmeta(
-                        asset_path_to_package_id(&asset_path), os_path.as_path())?;
+                    let unmounted = convert_to_package_id(&os_path, path.as_path(), None);
+                    folder_meta.add_from_uassetmeta(
+                        asset_path_to_package_id(&asset_path), &[unmounted], os_path.as_path(), profile.engine_version)?;
                 },
                 Some(_) => {
                     let asset_path = convert_to_asset_path(&os_path, path.as_path(), mount.as_ref());
+                    if mount.is_some() {
+                        mounted_ids.insert(convert_to_package_id(&os_path, path.as_path(), None),
+                            asset_path_to_package_id(&asset_path));
+                    }
                     let file_size = os_file_size(&file.metadata()?);
-                    Self::instance().as_mut().unwrap().insert(asset_path, AssetEntry::new(os_path, file_size));
+                    Self::insert(asset_path, AssetEntry::new(os_path, file_size), Some(path.as_path()));
                 },
                 None => match os_path.file_name().map(|f| f.to_str().unwrap()) {
                     Some(UTOCMETA) => {
-                        MetadataState::instance().as_mut().unwrap().add_from_utocmeta(
-                            std::fs::read(file.path())?.as_slice(), version)?;
+                        let header = folder_meta.add_from_utocmeta(
+                            std::fs::read(file.path())?.as_slice(), profile.engine_version)?;
+                        if let Some(header_version) = header.container_header_version
+                            && header_version != profile.container_header_version() {
+                            log!(Warning, "{} was made for container header version {:?} ({}), reading it's store entries for {:?}",
+                                file.path().to_str().unwrap(), header_version,
+                                header.tool_version.as_deref().unwrap_or("unknown tool"), profile.engine_version);
+                        }
                     },
                     _ => {}
                 }
             }
         }
+        // Metadata from the extractor is keyed without the mount unless it was made with --virtual-path
+        if !mounted_ids.is_empty() {
+            folder_meta = folder_meta.rekey(&mounted_ids);
+        }
+        MetadataState::instance().as_mut().unwrap().merge(folder_meta, MergePolicy::Replace)?;
+        BuildReport::record_time(format!("Collect {}", path.to_str().unwrap()), start);
+        Ok(())
+    }
+
+    fn add_raw_chunk(file: &DirEntry) -> GenericResult<()> {
+        let name = file.file_name().to_str().unwrap();
+        match from_raw_chunk_file_name(name) {
+            Some(chunk_id) => {
+                let file_size = os_file_size(&file.metadata()?);
+                Self::raw_chunks().as_mut().unwrap().insert(chunk_id, AssetEntry::new(file.path().to_owned(), file_size));
+            },
+            None => {
+                log!(Warning, "Skipping {}: raw chunks must be named <chunk id>_<chunk index>.<chunk type>", file.path().to_str().unwrap());
+            }
+        }
+        Ok(())
+    }
+
+    /// Register an asset that's stored in memory. The path is relative to the mount point, in the same
+    /// form as a path inside of an UnrealEssentials folder (e.g P3R/Content/...)
+    pub(crate) fn add_from_memory(path: &str, data: Arc<[u8]>) -> GenericResult<()> {
+        let path = path.replace("\\", "/");
+        let extension = path.rsplit_once('.').map(|(_, ext)| ext);
+        if !extension.map_or(false, |e| ASSET_EXTENSIONS.contains(&e) && e != UASSETMETA_EXTENSION) {
+            return Err(anyhow!("{} must be a .uasset, .umap, .ubulk or .uptnl", path).into_boxed_dyn_error());
+        }
+        if path.splitn(3, '/').count() < 3 {
+            return Err(anyhow!("{} must start with the project and content folder (e.g P3R/Content/...)", path).into_boxed_dyn_error());
+        }
+        let asset_path = get_asset_path(&path, None);
+        Self::insert(asset_path, AssetEntry::from_memory(PathBuf::from(path), data), None);
         Ok(())
     }
 
     pub(crate) fn add_from_folder_with_mount<P0: AsRef<Path>, P1: AsRef<Path>>(
-        path: P0, mount: P1, version: EngineVersion) -> GenericResult<()> {
+        path: P0, mount: P1, profile: &GameProfile) -> GenericResult<()> {
         let (path, mount) = (path.as_ref().to_owned(), mount.as_ref().to_owned());
-        if !path.exists() || !mount.exists() { return Ok(()); }
-        Self::add_from_folder_inner(path, Some(mount), version)
+        // The mount is a virtual path, so only the mod folder has to exist
+        if !path.exists() { return Ok(()); }
+        Self::add_from_folder_inner(path, Some(mount), profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use utoc_lib::assets::asset_path_to_package_id;
+    use utoc_lib::profiles::GameProfile;
+    use crate::GenericResult;
+    use super::AssetCollection;
+
+    fn source_folder(asset_path: &str) -> Option<Option<PathBuf>> {
+        AssetCollection::emulated_assets().into_iter()
+            .find(|a| a.asset_path == asset_path)
+            .map(|a| a.source_folder)
+    }
+
+    #[test]
+    fn is_path_emulated() -> GenericResult<()> {
+        AssetCollection::add_from_memory("UtocTest/Content/AssetIndex/Lookup.uasset", Arc::new([0u8; 4]))?;
+        AssetCollection::add_from_memory("UtocTest/Content/AssetIndex/BulkOnly.ubulk", Arc::new([0u8; 4]))?;
+        let package_id = asset_path_to_package_id("../../../Game/AssetIndex/Lookup.uasset");
+        assert!(AssetCollection::is_package_emulated(package_id));
+        assert!(AssetCollection::is_path_emulated("/Game/AssetIndex/Lookup"));
+        assert!(AssetCollection::is_path_emulated(&format!("0x{:X}", package_id.0)));
+        assert!(AssetCollection::is_path_emulated("UtocTest/Content/AssetIndex/Lookup.uasset"));
+        assert!(AssetCollection::is_path_emulated("../../../UtocTest/Content/AssetIndex/Lookup.uasset"));
+        assert!(AssetCollection::is_path_emulated("UtocTest\\Content\\AssetIndex\\BulkOnly.ubulk"));
+        // Bulk data isn't a package, so it can only be found by its path
+        assert!(!AssetCollection::is_path_emulated("/Game/AssetIndex/BulkOnly"));
+        assert!(!AssetCollection::is_path_emulated("/Game/AssetIndex/Missing"));
+        assert!(!AssetCollection::is_path_emulated("UtocTest/Content"));
+        Ok(())
+    }
+
+    #[test]
+    fn replaced_asset_source() -> GenericResult<()> {
+        let profile = GameProfile::find_by_name("P3R").unwrap();
+        let folder = std::env::temp_dir().join(format!("utoc-emulator-asset-source-{}", std::process::id()));
+        std::fs::create_dir_all(folder.join("UtocTest/Content/AssetIndex"))?;
+        std::fs::write(folder.join("UtocTest/Content/AssetIndex/Replaced.uasset"), [0; 8])?;
+        let asset_path = "../../../Game/AssetIndex/Replaced.uasset";
+
+        AssetCollection::add_from_folder(&folder, profile)?;
+        assert_eq!(source_folder(asset_path), Some(Some(folder.clone())));
+        // Replacing it from memory means that it no longer comes from a folder
+        AssetCollection::add_from_memory("UtocTest/Content/AssetIndex/Replaced.uasset", Arc::new([0u8; 4]))?;
+        assert_eq!(source_folder(asset_path), Some(None));
+        assert_eq!(AssetCollection::emulated_assets().iter().find(|a| a.asset_path == asset_path).unwrap().size, 4);
+        AssetCollection::add_from_folder(&folder, profile)?;
+        assert_eq!(source_folder(asset_path), Some(Some(folder.clone())));
+        std::fs::remove_dir_all(&folder)?;
+        Ok(())
     }
-}
\ No newline at end of file
+}
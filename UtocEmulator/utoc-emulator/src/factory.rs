@@ -1,22 +1,26 @@
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom};
+use std::time::Instant;
 use anyhow::{anyhow, Context};
 use console::Term;
 use indicatif::{ProgressBar, ProgressStyle};
 use retoc::{lower_utf16_cityhash, EIoChunkType, EIoStoreTocVersion, FIoChunkHash, FIoChunkId, FIoContainerId, FIoOffsetAndLength, FIoStoreTocCompressedBlockEntry, FIoStoreTocEntryMeta, FIoStoreTocEntryMetaFlags, FPackageId, Toc, UEPath, UEPathBuf};
 use retoc::container_header::{EIoContainerHeaderVersion, FIoContainerHeader, StoreEntry};
 use retoc::ser::{ReadExt, WriteExt};
-use retoc::version::EngineVersion;
 use retoc::zen::{ExternalPackageDependency, FExportBundleEntry, FExportBundleHeader, FExportMapEntry, FInternalDependencyArc, FZenPackageImportedPackageNamesContainer, FZenPackageSummary, FZenPackageVersioningInfo};
 use utoc_lib::assets::*;
 use utoc_lib::assets::AssetEntry;
 use utoc_lib::metadata::UtocMetaImportType;
+use utoc_lib::profiles::GameProfile;
 use utoc_lib::store::{StoreEntryBuilder, StoreEntryBuilderNew, StoreEntryBuilderOld};
 use crate::ffi::{Array, PartitionBlock};
 use crate::GenericResult;
+use crate::log;
 use crate::assets::AssetCollection;
 use crate::metadata::{MetadataAdapter, MetadataState};
+use crate::report::{AssetReport, BuildReport};
 
 pub(crate) fn size_of_export_bundle_header(header_version: EIoContainerHeaderVersion) -> u32 {
     match header_version {
@@ -103,10 +107,18 @@ impl IoStoreWriter {
         Ok(cas_start)
     }
 
-    pub fn write_file_chunk(&mut self, chunk_id: FIoChunkId, path: &UEPath, data: &AssetEntry) -> GenericResult<()> {
+    /// Returns the chunk's offset in the .ucas
+    pub fn write_file_chunk(&mut self, chunk_id: FIoChunkId, path: &UEPath, data: &AssetEntry) -> GenericResult<u64> {
         let start = self.write_chunk(chunk_id, Some(path), ChunkData::Asset(data))?;
-        self.partitions.push(PartitionBlock::new(data.os_path.to_str().unwrap(), start, data.size));
-        Ok(())
+        self.partitions.push(PartitionBlock::from_asset(data, start));
+        Ok(start)
+    }
+
+    /// Write a chunk that has no path in the directory index
+    pub fn write_raw_chunk(&mut self, chunk_id: FIoChunkId, data: &AssetEntry) -> GenericResult<u64> {
+        let start = self.write_chunk(chunk_id, None, ChunkData::Asset(data))?;
+        self.partitions.push(PartitionBlock::from_asset(data, start));
+        Ok(start)
     }
 
     pub fn write_file_container(&mut self, data: &[u8]) -> GenericResult<()> {
@@ -117,7 +129,7 @@ impl IoStoreWriter {
         Ok(())
     }
 
-    pub fn write_package_chunk(&mut self, chunk_id: FIoChunkId, path: &UEPath, data: &AssetEntry, store: &StoreEntry) -> GenericResult<()> {
+    pub fn write_package_chunk(&mut self, chunk_id: FIoChunkId, path: &UEPath, data: &AssetEntry, store: &StoreEntry) -> GenericResult<u64> {
         let container_header = self.container_header.as_mut()
             .expect("FIoContainerHeader is required to write package chunks");
         container_header.add_package(FPackageId(chunk_id.get_chunk_id()), store.clone());
@@ -142,32 +154,69 @@ impl IoStoreWriter {
 
 pub struct IoStoreFactory;
 impl IoStoreFactory {
-    fn insert_uasset(writer: &mut IoStoreWriter, chunk_id: FIoChunkId, asset_path: &str,
-        asset_entry: &AssetEntry, header_version: EIoContainerHeaderVersion) -> GenericResult<()> {
-        let adapter = MetadataAdapter;
-        let store_entry = match header_version {
+    fn rebuild_store_entry(asset_entry: &AssetEntry, package_id: FPackageId,
+        header_version: EIoContainerHeaderVersion, report_imports: bool) -> GenericResult<StoreEntry> {
+        let adapter = MetadataAdapter { report_imports };
+        match header_version {
             EIoContainerHeaderVersion::Initial =>
-                StoreEntryBuilderOld::rebuild_store_entry(asset_entry, chunk_id.get_package_id(), header_version, &adapter)?,
-            _ => StoreEntryBuilderNew::rebuild_store_entry(asset_entry, chunk_id.get_package_id(), header_version, &adapter)?,
+                StoreEntryBuilderOld::rebuild_store_entry(asset_entry, package_id, header_version, &adapter),
+            _ => StoreEntryBuilderNew::rebuild_store_entry(asset_entry, package_id, header_version, &adapter),
+        }
+    }
+
+    /// The store entry that an emulated package gets when the container is built, or None if the
+    /// package isn't emulated
+    pub(crate) fn get_store_entry(package_id: FPackageId, profile: &GameProfile) -> GenericResult<Option<StoreEntry>> {
+        let asset_path = match AssetCollection::get_package_asset(package_id) {
+            Some(v) => v,
+            None => return Ok(None)
         };
-        writer.write_package_chunk(chunk_id, UEPath::new(asset_path), asset_entry, &store_entry)?;
-        Ok(())
+        // Cloned so that the asset list isn't locked while the package is read
+        let entry = match AssetCollection::instance().as_ref().unwrap().get(&asset_path) {
+            Some(v) => v.clone(),
+            None => return Ok(None)
+        };
+        Ok(Some(Self::rebuild_store_entry(&entry, package_id, profile.container_header_version(), false)?))
     }
 
-    fn insert_bulk(writer: &mut IoStoreWriter, chunk_id: FIoChunkId, asset_path: &str, asset_entry: &AssetEntry) -> GenericResult<()> {
-        _ = writer.write_file_chunk(chunk_id, UEPath::new(asset_path), asset_entry);
-        Ok(())
+    fn insert_uasset(writer: &mut IoStoreWriter, chunk_id: FIoChunkId, asset_path: &str,
+        asset_entry: &AssetEntry, header_version: EIoContainerHeaderVersion) -> GenericResult<AssetReport> {
+        let store_entry = Self::rebuild_store_entry(asset_entry, chunk_id.get_package_id(), header_version, true)?;
+        let start = writer.write_package_chunk(chunk_id, UEPath::new(asset_path), asset_entry, &store_entry)?;
+        let mut report = AssetReport::new(Some(asset_path), asset_entry.os_path.as_path(),
+            chunk_id.get_package_id(), chunk_id.get_chunk_type(), asset_entry.size, start);
+        // Same order as the checks in StoreEntryBuilder. UE5 packages only use metadata for v2 imports
+        let import_type = MetadataState::instance().as_ref().unwrap().get_import_type(chunk_id.get_package_id());
+        report.import_type = match (header_version, import_type) {
+            (_, UtocMetaImportType::ManualV2) | (EIoContainerHeaderVersion::Initial, _) => Some(format!("{:?}", import_type)),
+            (v, _) if v < EIoContainerHeaderVersion::NoExportInfo => Some("Heuristic".to_owned()),
+            _ => None
+        };
+        report.store_entry = Some((&store_entry).into());
+        Ok(report)
+    }
+
+    fn insert_bulk(writer: &mut IoStoreWriter, chunk_id: FIoChunkId, asset_path: &str, asset_entry: &AssetEntry) -> GenericResult<Option<AssetReport>> {
+        Ok(writer.write_file_chunk(chunk_id, UEPath::new(asset_path), asset_entry).ok()
+            .map(|start| AssetReport::new(Some(asset_path), asset_entry.os_path.as_path(),
+                chunk_id.get_package_id(), chunk_id.get_chunk_type(), asset_entry.size, start)))
     }
 
     pub(crate) fn build(
-        version: EngineVersion,
+        profile: &GameProfile,
         toc: &mut Array<u8>,
         blocks: &mut Array<PartitionBlock>,
         header: &mut Array<u8>
     ) -> GenericResult<()> {
+        let build_start = Instant::now();
+        BuildReport::with(|r| {
+            r.game_profile = profile.is_game().then(|| profile.name.to_owned());
+            r.engine_version = Some(format!("{:?}", profile.engine_version));
+            r.container_header_version = Some(format!("{:?}", profile.container_header_version()));
+        });
         let mut writer = IoStoreWriter::new(
-            version.toc_version(),
-            Some(version.container_header_version()),
+            profile.toc_version(),
+            Some(profile.container_header_version()),
             UEPath::new(MOUNT_POINT).into()
         )?;
 
@@ -181,6 +230,9 @@ impl IoStoreFactory {
         bar.set_style(bar_style);
         bar.tick();
 
+        let mut written_chunks = HashSet::new();
+        let mut reports = vec![];
+        let assets_start = Instant::now();
         for (asset_name, asset_entry) in AssetCollection::instance().as_ref().unwrap() {
             // log!(Debug, "IoStoreFactory::build: {}", asset_name);
             let chunk_type = match asset_entry.os_path.extension().map(|v| v.to_str().unwrap())
@@ -195,17 +247,49 @@ impl IoStoreFactory {
             };
             let asset_name_tr = asset_name[MOUNT_POINT.len() - 1..].rsplit_once('.').unwrap().0;
             let chunk_id = FIoChunkId::create(lower_utf16_cityhash(asset_name_tr), 0, chunk_type.clone());
-            match chunk_type {
-                EIoChunkType::ExportBundleData => Self::insert_uasset(
-                    &mut writer, chunk_id, asset_name, asset_entry, version.container_header_version())?,
+            written_chunks.insert(chunk_id);
+            let report = match chunk_type {
+                EIoChunkType::ExportBundleData => Some(Self::insert_uasset(
+                    &mut writer, chunk_id, asset_name, asset_entry, profile.container_header_version())?),
                 EIoChunkType::BulkData | EIoChunkType::OptionalBulkData => Self::insert_bulk(
                     &mut writer, chunk_id, asset_name, asset_entry)?,
-                _ => ()
+                _ => None
             };
+            reports.extend(report);
             bar.set_message(asset_name_tr.to_owned());
             bar.set_position(bar.position() + 1);
         }
+        BuildReport::record_time("Write assets".to_owned(), assets_start);
+        let raw_start = Instant::now();
+        Self::insert_raw_chunks(&mut writer, &written_chunks, &mut reports)?;
+        BuildReport::record_time("Write raw chunks".to_owned(), raw_start);
+        let finalize_start = Instant::now();
         writer.finalize(toc, blocks, header)?;
+        BuildReport::record_time("Finalize".to_owned(), finalize_start);
+        BuildReport::record_time("Build".to_owned(), build_start);
+        BuildReport::with(|r| r.assets = reports);
+        Ok(())
+    }
+
+    fn insert_raw_chunks(writer: &mut IoStoreWriter, written_chunks: &HashSet<FIoChunkId>,
+        reports: &mut Vec<AssetReport>) -> GenericResult<()> {
+        for (chunk_id, asset_entry) in AssetCollection::raw_chunks().as_ref().unwrap() {
+            let name = asset_entry.os_path.to_str().unwrap();
+            match chunk_id.get_chunk_type() {
+                // Packages need a store entry, and the container header is created by IoStoreWriter
+                EIoChunkType::ExportBundleData | EIoChunkType::ContainerHeader => {
+                    log!(Warning, "Skipping {}: {:?} chunks can't be added as a raw chunk", name, chunk_id.get_chunk_type());
+                },
+                _ if written_chunks.contains(chunk_id) => {
+                    log!(Warning, "Skipping {}: a loose file already exists for chunk {:?}", name, chunk_id);
+                },
+                _ => {
+                    let start = writer.write_raw_chunk(*chunk_id, asset_entry)?;
+                    reports.push(AssetReport::new(None, asset_entry.os_path.as_path(),
+                        chunk_id.get_package_id(), chunk_id.get_chunk_type(), asset_entry.size, start));
+                }
+            }
+        }
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,57 +1,19 @@
-use std::path::Path;
+use std::any::Any;
+use std::error::Error;
+use std::ffi::{c_char, CString};
+use std::panic::AssertUnwindSafe;
 use std::ptr::NonNull;
+use std::sync::Arc;
 use std::sync::OnceLock;
-use retoc::container_header::EIoContainerHeaderVersion;
-use retoc::EIoStoreTocVersion;
+use std::sync::atomic::Ordering;
+use retoc::FPackageId;
+use utoc_lib::assets::AssetEntry;
+use utoc_lib::profiles::GameProfile;
 use crate::log;
-use crate::assets::AssetCollection;
+use crate::assets::{AssetCollection, EmulatedAsset};
 use crate::factory::IoStoreFactory;
-
-/// This must stay in sync with EngineVersion in UTOC.Stream.Emulator.Interfaces over in C# land!
-#[repr(u32)]
-#[allow(non_camel_case_types)]
-pub enum EngineVersion
-{
-    UE_4_25 = (4 << 0x8) + 25,
-    UE_4_26 = (4 << 0x8) + 26, // 4.25+ (e.g Scarlet Nexus) is treated as 4.26
-    UE_4_27 = (4 << 0x8) + 27,
-    UE_5_0 = (5 << 0x8) + 0,
-    UE_5_1 = (5 << 0x8) + 1,
-    UE_5_2 = (5 << 0x8) + 2,
-    UE_5_3 = (5 << 0x8) + 3,
-    UE_5_4 = (5 << 0x8) + 4,
-    UE_5_5 = (5 << 0x8) + 5,
-    UE_5_6 = (5 << 0x8) + 6,
-    UE_5_7 = (5 << 0x8) + 7,
-    // if there are any games that require a special ID, then define them with [value] << 0x10
-    // FF7R = (4 << 0x8) + 27 | 1 << 0x10
-}
-
-impl EngineVersion {
-    pub fn to_retoc(&self) -> retoc::version::EngineVersion {
-        match self {
-            Self::UE_4_25 => retoc::version::EngineVersion::UE4_25,
-            Self::UE_4_26 => retoc::version::EngineVersion::UE4_26,
-            Self::UE_4_27 => retoc::version::EngineVersion::UE4_27,
-            Self::UE_5_0  => retoc::version::EngineVersion::UE5_0,
-            Self::UE_5_1  => retoc::version::EngineVersion::UE5_1,
-            Self::UE_5_2  => retoc::version::EngineVersion::UE5_2,
-            Self::UE_5_3  => retoc::version::EngineVersion::UE5_3,
-            Self::UE_5_4  => retoc::version::EngineVersion::UE5_4,
-            Self::UE_5_5  => retoc::version::EngineVersion::UE5_5,
-            Self::UE_5_6  => retoc::version::EngineVersion::UE5_6,
-            Self::UE_5_7  => retoc::version::EngineVersion::UE5_7,
-        }
-    }
-
-    pub fn to_toc_version(&self) -> EIoStoreTocVersion {
-        self.to_retoc().toc_version()
-    }
-
-    pub fn to_cas_header_version(&self) -> EIoContainerHeaderVersion {
-        self.to_retoc().container_header_version()
-    }
-}
+use crate::metadata::HEURISTIC_IMPORTS;
+use crate::report::BuildReport;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -70,8 +32,10 @@ impl<T> Default for Array<T> {
 }
 
 impl<T> From<Vec<T>> for Array<T> {
+    /// Ownership is passed to the caller, who has to give it back with Array::take (e.g through
+    /// free_array_u8) once they're done with it
     fn from(value: Vec<T>) -> Self {
-        let boxed = value.leak();
+        let boxed = Box::leak(value.into_boxed_slice());
         Self {
             data: boxed.as_ptr() as _,
             len: boxed.len()
@@ -79,23 +43,85 @@ impl<T> From<Vec<T>> for Array<T> {
     }
 }
 
+impl<T> Array<T> {
+    /// Take back ownership of an array made from a Vec, leaving it empty so that it can't be freed
+    /// twice. Safety: the array must be empty or have come from Array::from
+    pub(crate) unsafe fn take(&mut self) -> Option<Box<[T]>> {
+        if self.data.is_null() {
+            return None;
+        }
+        let boxed = unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.data as *mut T, self.len)) };
+        *self = Self::default();
+        Some(boxed)
+    }
+}
+
+/// A range of the .ucas that's read from either a file (os_path) or memory (data). This must stay in
+/// sync with PartitionBlock in UtocEmulator.cs!
 #[repr(C)]
 pub struct PartitionBlock {
     pub(crate) os_path: *const u8,
     pub(crate) start: u64,
     pub(crate) length: u64,
+    /// Holds a reference to an in-memory asset's contents, so the data stays alive until the block
+    /// is freed even if the asset is replaced
+    pub(crate) data: *const u8,
 }
 
 impl PartitionBlock {
     pub fn new(path: &str, start: u64, length: u64) -> Self {
         Self {
-            os_path: format!("{}\0", path).encode_utf16().collect::<Vec<u16>>().leak().as_ptr() as _,
+            os_path: Box::leak(format!("{}\0", path).encode_utf16().collect::<Box<[u16]>>()).as_ptr() as _,
             start,
-            length
+            length,
+            data: std::ptr::null()
+        }
+    }
+
+    pub fn from_memory(data: &Arc<[u8]>, start: u64) -> Self {
+        Self {
+            os_path: std::ptr::null(),
+            start,
+            length: data.len() as u64,
+            data: Arc::into_raw(data.clone()) as *const u8
+        }
+    }
+
+    pub fn from_asset(asset: &AssetEntry, start: u64) -> Self {
+        match &asset.data {
+            Some(data) => Self::from_memory(data, start),
+            None => Self::new(asset.os_path.to_str().unwrap(), start, asset.size)
         }
     }
 }
 
+impl Drop for PartitionBlock {
+    fn drop(&mut self) {
+        if !self.data.is_null() {
+            drop(unsafe { Arc::from_raw(std::ptr::slice_from_raw_parts(self.data, self.length as usize)) });
+        }
+        if self.os_path.is_null() {
+            return;
+        }
+        // os_path is a null terminated UTF-16 string, so the allocation includes the terminator
+        let path = self.os_path as *mut u16;
+        let mut len = 0;
+        while unsafe { *path.add(len) } != 0 {
+            len += 1;
+        }
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(path, len + 1)) });
+    }
+}
+
+/// Incremented whenever an export's signature or a struct passed across the FFI changes. C# checks
+/// this on startup against the version that RustApi.cs was written for
+pub const ABI_VERSION: u32 = 2;
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn utoc_emulator_abi_version() -> u32 {
+    ABI_VERSION
+}
+
 // CSharpString from riri-mod-tools:
 // https://github.com/rirurin/riri-mod-tools/blob/main/riri-mod-tools-rt/src/mod_loader_data.rs
 type FreeStrFn = unsafe extern "C" fn(*const u16) -> ();
@@ -104,7 +130,8 @@ pub static FREE_CSHARP_STRING: OnceLock<FreeStrFn> = OnceLock::new();
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn set_free_csharp_string(cb: FreeStrFn) {
-    FREE_CSHARP_STRING.set(cb).unwrap();
+    // Only the first callback is kept, since strings may already be in flight
+    ffi_guard_void("set_free_csharp_string", || { let _ = FREE_CSHARP_STRING.set(cb); })
 }
 
 #[repr(C)]
@@ -114,65 +141,343 @@ impl CSharpString {
     pub fn new(p: *const u16) -> Self {
         Self(p)
     }
-}
-impl From<CSharpString> for String {
-    fn from(value: CSharpString) -> Self {
+
+    /// None if the pointer is null or the string isn't valid UTF-16
+    pub fn try_to_string(&self) -> Option<String> {
+        if self.0.is_null() {
+            return None;
+        }
         let mut len = 0;
-        while unsafe { *value.0.add(len) } != 0 {
+        while unsafe { *self.0.add(len) } != 0 {
             len += 1;
         }
-        let s = unsafe { std::slice::from_raw_parts(value.0, len) };
-        String::from_utf16(s).unwrap()
+        let s = unsafe { std::slice::from_raw_parts(self.0, len) };
+        String::from_utf16(s).ok()
+    }
+}
+impl From<CSharpString> for String {
+    fn from(value: CSharpString) -> Self {
+        value.try_to_string().unwrap()
     }
 }
 impl Drop for CSharpString {
     fn drop(&mut self) {
-        unsafe { FREE_CSHARP_STRING.get().unwrap()(self.0) }
+        if self.0.is_null() {
+            return;
+        }
+        if let Some(free) = FREE_CSHARP_STRING.get() {
+            unsafe { free(self.0) }
+        }
+    }
+}
+
+/// Result of an exported function. These values are part of the FFI, so existing variants must
+/// keep their value. This must stay in sync with FfiStatus in RustApi.cs!
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FfiStatus {
+    Ok = 0,
+    /// A pointer was null or a string wasn't valid UTF-16
+    InvalidArgument = 1,
+    /// A file couldn't be read or written
+    Io = 2,
+    /// Any other error, such as an asset that couldn't be parsed or invalid asset metadata
+    Error = 3,
+    /// Rust panicked. The emulator's state may be incomplete after this
+    Panic = 4,
+    /// The package or asset that was asked for isn't emulated
+    NotFound = 5,
+}
+
+impl FfiStatus {
+    pub fn from_error(error: &(dyn Error + 'static)) -> Self {
+        match error.downcast_ref::<std::io::Error>() {
+            Some(_) => Self::Io,
+            None => Self::Error
+        }
     }
 }
 
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(v) => v.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_else(|| "Unknown panic".to_owned())
+    }
+}
+
+/// Run an export's body, turning errors and panics into a status. If error isn't null, it's set to
+/// an owned UTF-8 message (or null on success) that must be freed with free_error_message. The message
+/// isn't logged here since the caller logs it
+pub(crate) fn ffi_guard<F: FnOnce() -> Result<(), (FfiStatus, String)>>(error: *mut *mut c_char, f: F) -> FfiStatus {
+    let (status, message) = match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (FfiStatus::Ok, None),
+        Ok(Err((status, message))) => (status, Some(message)),
+        Err(payload) => (FfiStatus::Panic, Some(format!("Panic: {}", panic_message(payload.as_ref()))))
+    };
+    if !error.is_null() {
+        // Interior nul bytes would cut the message short, so they're replaced
+        let message = message.map_or(std::ptr::null_mut(), |m| CString::new(m.replace('\0', " "))
+            .unwrap().into_raw());
+        unsafe { *error = message };
+    }
+    status
+}
+
+/// For exports that can't fail. Panics are logged since there's no status to return
+pub(crate) fn ffi_guard_void<F: FnOnce()>(name: &str, f: F) {
+    ffi_guard_or(name, (), f)
+}
+
+/// Same as ffi_guard_void, returning default if there was a panic
+pub(crate) fn ffi_guard_or<T, F: FnOnce() -> T>(name: &str, default: T, f: F) -> T {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(v) => v,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+            let _ = std::panic::catch_unwind(|| { log!(Error, "{} panicked: {}", name, message); });
+            default
+        }
+    }
+}
+
+/// Values coming from C# are checked against GameProfile's table of engine versions and games
+fn check_version(version: u32) -> Result<GameProfile, (FfiStatus, String)> {
+    GameProfile::from_id(version).ok_or_else(|| (FfiStatus::InvalidArgument, match version >> 0x10 {
+        0 => format!("Engine version {}.{} (0x{:X}) is not supported by UTOC Emulator", (version >> 8) & 0xff, version & 0xff, version),
+        _ => format!("Game profile 0x{:X} is not supported by this version of UTOC Emulator", version)
+    }))
+}
+
+fn check_string(value: &CSharpString, name: &str) -> Result<String, (FfiStatus, String)> {
+    value.try_to_string().ok_or_else(|| (FfiStatus::InvalidArgument, format!("{} is null or not valid UTF-16", name)))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_error_message(message: *mut c_char) {
+    ffi_guard_void("free_error_message", || if !message.is_null() {
+        drop(unsafe { CString::from_raw(message) });
+    })
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn add_from_folders(
     mod_path: CSharpString,
-    version: EngineVersion) {
-    let mod_path = Into::<String>::into(mod_path);
-    if let Err(e) = AssetCollection::add_from_folder(
-        &mod_path, version.to_retoc()) {
-        log!(Error, "An error occurred while collecting assets from {}: {}", mod_path, e.to_string());
-    }
+    version: u32,
+    error: *mut *mut c_char) -> FfiStatus {
+    ffi_guard(error, || {
+        let mod_path = check_string(&mod_path, "mod_path")?;
+        AssetCollection::add_from_folder(&mod_path, &check_version(version)?).map_err(|e| {
+            (FfiStatus::from_error(e.as_ref()), format!("An error occurred while collecting assets from {}: {}", mod_path, e.to_string()))
+        })
+    })
 }
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn add_from_folders_with_mount(
     mod_path: CSharpString,
     virtual_path: CSharpString,
-    version: EngineVersion) {
-    let mod_path = Into::<String>::into(mod_path);
-    let virtual_path = Into::<String>::into(virtual_path);
-    if let Err(e) = AssetCollection::add_from_folder_with_mount(
-        &mod_path, &virtual_path, version.to_retoc()
-    ) {
-        log!(Error, "An error occurred while collecting assets from {} with virtual path {}: {}"
-            , mod_path, virtual_path, e.to_string());
-    }
+    version: u32,
+    error: *mut *mut c_char) -> FfiStatus {
+    ffi_guard(error, || {
+        let mod_path = check_string(&mod_path, "mod_path")?;
+        let virtual_path = check_string(&virtual_path, "virtual_path")?;
+        AssetCollection::add_from_folder_with_mount(&mod_path, &virtual_path, &check_version(version)?).map_err(|e| {
+            (FfiStatus::from_error(e.as_ref()), format!("An error occurred while collecting assets from {} with virtual path {}: {}",
+                mod_path, virtual_path, e.to_string()))
+        })
+    })
+}
+
+/// Register an asset from a buffer instead of a file. asset_path is relative to the mount point in
+/// the same way as a file in an UnrealEssentials folder (e.g P3R/Content/Characters/Player.uasset).
+/// The buffer is copied, so it can be freed once this returns
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn add_from_memory(
+    asset_path: CSharpString,
+    data: *const u8,
+    len: usize,
+    error: *mut *mut c_char) -> FfiStatus {
+    ffi_guard(error, || {
+        let asset_path = check_string(&asset_path, "asset_path")?;
+        if data.is_null() && len > 0 {
+            return Err((FfiStatus::InvalidArgument, format!("No data was given for {}", asset_path)));
+        }
+        let data: Arc<[u8]> = match len {
+            0 => Arc::new([]),
+            _ => Arc::from(unsafe { std::slice::from_raw_parts(data, len) })
+        };
+        AssetCollection::add_from_memory(&asset_path, data)
+            .map_err(|e| (FfiStatus::InvalidArgument, e.to_string()))
+    })
 }
 
+/// The TOC, partition blocks and container header are owned by the caller, who must free them with
+/// free_array_u8 and free_partition_blocks once the emulated files are no longer used
 #[unsafe(no_mangle)]
 // haiiii Reloaded!!!! :3
 pub unsafe extern "C" fn build_toc(
-    version: EngineVersion,
+    version: u32,
     mut toc: NonNull<Array<u8>>,
     mut blocks: NonNull<Array<PartitionBlock>>,
-    mut header: NonNull<Array<u8>>
-) -> bool {
-    let result = IoStoreFactory::build(
-        version.to_retoc(),
-        unsafe { toc.as_mut() },
-        unsafe { blocks.as_mut() },
-        unsafe { header.as_mut() }
-    );
-    if let Err(e) = &result {
-        log!(Error, "An error occurred while building the IO Store package: {}", e.to_string());
-    }
-    result.is_ok()
-}
\ No newline at end of file
+    mut header: NonNull<Array<u8>>,
+    error: *mut *mut c_char
+) -> FfiStatus {
+    ffi_guard(error, || {
+        // The outputs are always valid to free afterwards, even if the build fails part way through
+        unsafe {
+            *toc.as_mut() = Array::default();
+            *blocks.as_mut() = Array::default();
+            *header.as_mut() = Array::default();
+        }
+        let result = IoStoreFactory::build(
+            &check_version(version)?,
+            unsafe { toc.as_mut() },
+            unsafe { blocks.as_mut() },
+            unsafe { header.as_mut() }
+        );
+        if let Err(e) = BuildReport::save(&result) {
+            log!(Error, "An error occurred while writing the build report: {}", e.to_string());
+        }
+        result.map_err(|e| {
+            (FfiStatus::from_error(e.as_ref()), format!("An error occurred while building the IO Store package: {}", e.to_string()))
+        })
+    })
+}
+
+/// Free the TOC or container header returned from build_toc. The array is reset so that freeing it
+/// again does nothing
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_array_u8(mut array: NonNull<Array<u8>>) {
+    ffi_guard_void("free_array_u8", || drop(unsafe { array.as_mut().take() }))
+}
+
+/// Free the partition blocks returned from build_toc, along with each block's path
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_partition_blocks(mut array: NonNull<Array<PartitionBlock>>) {
+    ffi_guard_void("free_partition_blocks", || drop(unsafe { array.as_mut().take() }))
+}
+
+/// Record a JSON report of the assets, warnings and timings for each build_toc call, written to
+/// report_path. Call this before adding any folders so that their warnings are included
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_build_report(report_path: CSharpString) {
+    ffi_guard_void("set_build_report", || match report_path.try_to_string() {
+        Some(path) => BuildReport::enable(path.into()),
+        None => { log!(Error, "set_build_report: report_path is null or not valid UTF-16"); }
+    })
+}
+
+/// Allow UE 5.0 - 5.2 packages without metadata to be built by guessing their imports. See
+/// HeuristicImportResolver
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_heuristic_imports(enabled: bool) {
+    ffi_guard_void("set_heuristic_imports", || HEURISTIC_IMPORTS.store(enabled, Ordering::Relaxed))
+}
+
+fn to_c_string(value: &str) -> *mut c_char {
+    CString::new(value).unwrap_or_default().into_raw()
+}
+
+/// An emulated asset returned from get_emulated_assets. This must stay in sync with EmulatedAssetInfo
+/// in RustApi.cs!
+#[repr(C)]
+pub struct EmulatedAssetInfo {
+    /// UTF-8, e.g ../../../Game/Characters/Player.uasset
+    pub(crate) asset_path: *mut c_char,
+    /// UTF-8, or null for assets added from memory
+    pub(crate) source_folder: *mut c_char,
+    pub(crate) package_id: u64,
+    pub(crate) size: u64,
+}
+
+impl From<EmulatedAsset> for EmulatedAssetInfo {
+    fn from(value: EmulatedAsset) -> Self {
+        Self {
+            asset_path: to_c_string(&value.asset_path),
+            source_folder: value.source_folder.map_or(std::ptr::null_mut(), |v| to_c_string(v.to_str().unwrap())),
+            package_id: value.package_id.0,
+            size: value.size
+        }
+    }
+}
+
+impl Drop for EmulatedAssetInfo {
+    fn drop(&mut self) {
+        for s in [self.asset_path, self.source_folder] {
+            if !s.is_null() {
+                drop(unsafe { CString::from_raw(s) });
+            }
+        }
+    }
+}
+
+/// A package's store entry, returned from get_store_entry. This must stay in sync with StoreEntryFfi
+/// in RustApi.cs!
+#[repr(C)]
+pub struct StoreEntryInfo {
+    pub(crate) export_bundles_size: u64,
+    pub(crate) export_count: i32,
+    pub(crate) export_bundle_count: i32,
+    pub(crate) imported_packages: Array<u64>,
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn is_package_emulated(package_id: u64) -> bool {
+    ffi_guard_or("is_package_emulated", false, || AssetCollection::is_package_emulated(FPackageId(package_id)))
+}
+
+/// Takes a package name (/Game/...), package id (0x...) or a path relative to the mount point
+/// (P3R/Content/...)
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn is_path_emulated(path: CSharpString) -> bool {
+    ffi_guard_or("is_path_emulated", false, || path.try_to_string()
+        .map_or(false, |p| AssetCollection::is_path_emulated(&p)))
+}
+
+/// Every emulated asset, sorted by asset path. Free with free_emulated_assets
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn get_emulated_assets(
+    mut assets: NonNull<Array<EmulatedAssetInfo>>,
+    error: *mut *mut c_char) -> FfiStatus {
+    ffi_guard(error, || {
+        let list: Vec<EmulatedAssetInfo> = AssetCollection::emulated_assets().into_iter().map(|v| v.into()).collect();
+        unsafe { *assets.as_mut() = list.into() };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_emulated_assets(mut assets: NonNull<Array<EmulatedAssetInfo>>) {
+    ffi_guard_void("free_emulated_assets", || drop(unsafe { assets.as_mut().take() }))
+}
+
+/// Compute the store entry that a package gets when the container is built. Returns NotFound if the
+/// package isn't emulated. Free with free_store_entry_info
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn get_store_entry(
+    package_id: u64,
+    version: u32,
+    mut entry: NonNull<StoreEntryInfo>,
+    error: *mut *mut c_char) -> FfiStatus {
+    ffi_guard(error, || {
+        let store_entry = match IoStoreFactory::get_store_entry(FPackageId(package_id), &check_version(version)?) {
+            Ok(Some(v)) => v,
+            Ok(None) => return Err((FfiStatus::NotFound, format!("Package 0x{:016X} is not emulated", package_id))),
+            Err(e) => return Err((FfiStatus::from_error(e.as_ref()), e.to_string()))
+        };
+        unsafe {
+            *entry.as_mut() = StoreEntryInfo {
+                export_bundles_size: store_entry.export_bundles_size,
+                export_count: store_entry.export_count,
+                export_bundle_count: store_entry.export_bundle_count,
+                imported_packages: store_entry.imported_packages.iter().map(|v| v.0).collect::<Vec<_>>().into()
+            }
+        };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_store_entry_info(mut entry: NonNull<StoreEntryInfo>) {
+    ffi_guard_void("free_store_entry_info", || drop(unsafe { entry.as_mut().imported_packages.take() }))
+}
@@ -3,6 +3,7 @@ pub(crate) mod factory;
 pub(crate) mod ffi;
 pub(crate) mod logger;
 pub(crate) mod metadata;
+pub(crate) mod report;
 
 use std::error::Error;
 
@@ -10,32 +11,107 @@ pub(crate) type GenericResult<T> = Result<T, Box<dyn Error>>;
 
 #[cfg(test)]
 pub mod tests {
+    use std::mem::offset_of;
     use std::path::Path;
     use std::ptr::NonNull;
-    use retoc::version::EngineVersion;
     use crate::GenericResult;
     use crate::assets::AssetCollection;
     use crate::factory::IoStoreFactory;
-    use crate::ffi::Array;
-    use crate::logger::{invoke_println, set_reloaded_logger};
+    use crate::ffi::{ffi_guard, Array, EmulatedAssetInfo, FfiStatus, PartitionBlock, StoreEntryInfo};
+    use crate::logger::{invoke_println, set_reloaded_logger, LogLevel};
+    use crate::metadata::MetadataState;
+    use retoc::version::EngineVersion;
+    use utoc_lib::profiles::GameProfile;
 
     #[test]
     fn package_test() -> GenericResult<()> {
-        // for Persona 3 Reload
-        let version = EngineVersion::UE4_27;
+        let profile = GameProfile::find_by_name("P3R").unwrap();
         unsafe { set_reloaded_logger(invoke_println) };
         AssetCollection::add_from_folder(
             Path::new("E:/Reloaded-II/Mods/p3rpc.isitworking/UnrealEssentials"),
-            version)?;
+            profile)?;
         let mut toc = Array::default();
         let mut partition = Array::default();
         let mut header = Array::default();
         IoStoreFactory::build(
-            version,
+            profile,
             &mut toc,
             &mut partition,
             &mut header
         )?;
+        unsafe {
+            toc.take();
+            partition.take();
+            header.take();
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn free_partition_blocks() {
+        let mut blocks: Array<PartitionBlock> = vec![
+            PartitionBlock::new("E:/Mod/UnrealEssentials/P3R/Content/A.uasset", 0, 0x100),
+            PartitionBlock::new("E:/Mod/UnrealEssentials/P3R/Content/B.ubulk", 0x100, 0x200)
+        ].into();
+        assert_eq!(blocks.len, 2);
+        let taken = unsafe { blocks.take() }.unwrap();
+        assert_eq!(taken[1].start, 0x100);
+        drop(taken);
+        // Taking again is a no-op rather than a double free
+        assert!(unsafe { blocks.take() }.is_none());
+    }
+
+    /// These layouts are mirrored by structs in RustApi.cs and UtocEmulator.cs. If this fails, update
+    /// the C# side and bump ABI_VERSION
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn ffi_struct_layouts() {
+        assert_eq!(size_of::<Array<u8>>(), 16);
+        assert_eq!(offset_of!(Array<u8>, data), 0);
+        assert_eq!(offset_of!(Array<u8>, len), 8);
+
+        assert_eq!(size_of::<PartitionBlock>(), 32);
+        assert_eq!(offset_of!(PartitionBlock, os_path), 0);
+        assert_eq!(offset_of!(PartitionBlock, start), 8);
+        assert_eq!(offset_of!(PartitionBlock, length), 16);
+        assert_eq!(offset_of!(PartitionBlock, data), 24);
+
+        assert_eq!(size_of::<EmulatedAssetInfo>(), 32);
+        assert_eq!(offset_of!(EmulatedAssetInfo, asset_path), 0);
+        assert_eq!(offset_of!(EmulatedAssetInfo, source_folder), 8);
+        assert_eq!(offset_of!(EmulatedAssetInfo, package_id), 16);
+        assert_eq!(offset_of!(EmulatedAssetInfo, size), 24);
+
+        assert_eq!(size_of::<StoreEntryInfo>(), 32);
+        assert_eq!(offset_of!(StoreEntryInfo, export_bundles_size), 0);
+        assert_eq!(offset_of!(StoreEntryInfo, export_count), 8);
+        assert_eq!(offset_of!(StoreEntryInfo, export_bundle_count), 12);
+        assert_eq!(offset_of!(StoreEntryInfo, imported_packages), 16);
+
+        assert_eq!(size_of::<FfiStatus>(), 4);
+        assert_eq!(size_of::<LogLevel>(), 4);
+    }
+
+    #[test]
+    fn engine_version_from_ffi() {
+        assert_eq!(GameProfile::from_id((4 << 8) + 27).unwrap().engine_version, EngineVersion::UE4_27);
+        assert_eq!(GameProfile::from_id((5 << 8) + 3).unwrap().engine_version, EngineVersion::UE5_3);
+        // UE 4.24 is in the C# enum, but it doesn't have IO Store
+        assert!(GameProfile::from_id((4 << 8) + 24).is_none());
+        assert!(GameProfile::from_id(u32::MAX).is_none());
+    }
+
+    #[test]
+    fn panic_while_locked() {
+        let status = ffi_guard(std::ptr::null_mut(), || {
+            let _assets = AssetCollection::instance();
+            let _metadata = MetadataState::instance();
+            panic!("Poison the asset list and metadata");
+        });
+        assert_eq!(status, FfiStatus::Panic);
+        // Later calls still work with whatever was left behind
+        assert!(AssetCollection::instance().is_some());
+        assert!(MetadataState::instance().is_some());
+        assert!(!AssetCollection::is_path_emulated("0x0"));
+    }
+}
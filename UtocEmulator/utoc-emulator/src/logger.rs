@@ -1,4 +1,9 @@
-use std::sync::OnceLock;
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::ffi::{ffi_guard_void, CSharpString};
+use crate::report::BuildReport;
 
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
@@ -19,12 +24,7 @@ pub enum LogLevel {
 macro_rules! log {
     ($ty:ident, $($fmt:tt)*) => {
         let text: String = format!($($fmt)*);
-        unsafe {
-            $crate::logger::invoke_reloaded_logger(
-                text.as_ptr(), text.len(),
-                $crate::logger::LogLevel::$ty,
-            );
-        }
+        $crate::logger::write($crate::logger::LogLevel::$ty, &text);
     };
 }
 
@@ -33,19 +33,129 @@ type LogFn = unsafe extern "C" fn(*const u8, usize, LogLevel) -> ();
 
 /// A function pointer to invoke WriteAsync method in Reloaded-II's logger. This allows for
 /// us to write into the console output and have that saved into a log file.
-pub static RELOADED_LOGGER: OnceLock<LogFn> = OnceLock::new();
+pub static RELOADED_LOGGER: RwLock<Option<LogFn>> = RwLock::new(None);
+
+/// Where logs go when there's no Reloaded logger set (e.g before the mod has registered it, or when
+/// the library is used outside of Reloaded). Defaults to stderr.
+static FALLBACK_LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Anything below this level is dropped before it reaches a sink.
+static MIN_LOG_LEVEL: AtomicU32 = AtomicU32::new(LogLevel::Debug as u32);
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn set_reloaded_logger(cb: LogFn) {
-    RELOADED_LOGGER.set(cb).unwrap();
+    ffi_guard_void("set_reloaded_logger", ||
+        *RELOADED_LOGGER.write().unwrap_or_else(|e| e.into_inner()) = Some(cb));
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn clear_reloaded_logger() {
+    ffi_guard_void("clear_reloaded_logger", ||
+        *RELOADED_LOGGER.write().unwrap_or_else(|e| e.into_inner()) = None);
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_log_level(level: u32) {
+    // Out of range values are clamped to Fatal rather than letting an invalid LogLevel exist
+    ffi_guard_void("set_log_level", ||
+        MIN_LOG_LEVEL.store(level.min(LogLevel::Fatal as u32), Ordering::Relaxed));
+}
+
+/// Send fallback logs to a file instead of stderr. Passing null goes back to stderr. Returns false if
+/// the file couldn't be created.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn set_fallback_log_file(path: *const u16) -> bool {
+    std::panic::catch_unwind(|| {
+        let file = match path.is_null() {
+            true => None,
+            false => match CSharpString::new(path).try_to_string().map(File::create) {
+                Some(Ok(f)) => Some(f),
+                _ => return false
+            }
+        };
+        *FALLBACK_LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()) = file;
+        true
+    }).unwrap_or(false)
 }
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn invoke_reloaded_logger(p: *const u8, len: usize, level: LogLevel) {
-    unsafe { RELOADED_LOGGER.get().unwrap()(p, len, level) };
+    ffi_guard_void("invoke_reloaded_logger", ||
+        write(level, unsafe { str::from_utf8_unchecked(std::slice::from_raw_parts(p, len)) }));
 }
 
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn invoke_println(p: *const u8, len: usize, _: LogLevel) {
-    println!("{}", unsafe { str::from_utf8_unchecked(std::slice::from_raw_parts(p, len)) });
-}
\ No newline at end of file
+    ffi_guard_void("invoke_println", ||
+        println!("{}", unsafe { str::from_utf8_unchecked(std::slice::from_raw_parts(p, len)) }));
+}
+
+pub fn is_enabled(level: LogLevel) -> bool {
+    level as u32 >= MIN_LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn write(level: LogLevel, text: &str) {
+    // Warnings go into the build report even if they're filtered out of the log
+    BuildReport::record_log(level, text);
+    if !is_enabled(level) {
+        return;
+    }
+    #[cfg(test)]
+    if capture::push(level, text) {
+        return;
+    }
+    if let Some(cb) = *RELOADED_LOGGER.read().unwrap_or_else(|e| e.into_inner()) {
+        unsafe { cb(text.as_ptr(), text.len(), level) };
+        return;
+    }
+    let mut file = FALLBACK_LOG_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    // Logging failures are ignored, there's nowhere left to report them
+    let _ = match file.as_mut() {
+        Some(f) => writeln!(f, "[UtocEmulator] [{:?}] {}", level, text),
+        None => writeln!(std::io::stderr(), "[UtocEmulator] [{:?}] {}", level, text)
+    };
+}
+
+/// Collects logs in memory for the current thread so that tests can check what was emitted. Captured
+/// logs don't reach the Reloaded logger or the fallback sink.
+#[cfg(test)]
+pub mod capture {
+    use std::cell::RefCell;
+    use super::LogLevel;
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Vec<(LogLevel, String)>>> = const { RefCell::new(None) };
+    }
+
+    pub(super) fn push(level: LogLevel, text: &str) -> bool {
+        CAPTURED.with_borrow_mut(|c| match c {
+            Some(logs) => { logs.push((level, text.to_owned())); true },
+            None => false
+        })
+    }
+
+    /// Run f, returning it's result along with every log emitted on this thread while it ran
+    pub fn capture_logs<R>(f: impl FnOnce() -> R) -> (R, Vec<(LogLevel, String)>) {
+        let previous = CAPTURED.replace(Some(vec![]));
+        let result = f();
+        let logs = CAPTURED.replace(previous).unwrap_or_default();
+        (result, logs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogLevel;
+    use super::capture::capture_logs;
+
+    #[test]
+    fn capture_and_filter() {
+        let (_, logs) = capture_logs(|| {
+            crate::log!(Warning, "Asset {} has no metadata", "Player");
+            crate::log!(Debug, "Not a warning");
+        });
+        let warnings: Vec<_> = logs.iter().filter(|(l, _)| *l == LogLevel::Warning).collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].1, "Asset Player has no metadata");
+    }
+}
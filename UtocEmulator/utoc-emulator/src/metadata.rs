@@ -4,14 +4,21 @@ use std::{
 use std::io::{Read, Seek};
 use std::ops::{Deref, DerefMut};
 use std::sync::MutexGuard;
+use std::sync::atomic::{AtomicBool, Ordering};
 use retoc::container_header::StoreEntry;
 use retoc::FPackageId;
 use retoc::zen::{ExternalPackageDependency, FZenPackageSummary};
 use utoc_lib::metadata::{UtocMetaImportType, UtocMetadata};
-use utoc_lib::store::{LegacyImportIdResolver, MetadataProvider};
+use utoc_lib::store::{HeuristicImports, ImportConfidence, LegacyImportIdResolver, MetadataProvider};
+use crate::log;
+use crate::report::{BuildReport, HeuristicImportReport};
 
 pub static UTOC_METADATA: MetadataState = MetadataState::new();
 
+/// Whether UE 5.0 - 5.2 packages without metadata have their imports guessed. Off by default, since
+/// a wrong guess loads the wrong packages instead of failing
+pub static HEURISTIC_IMPORTS: AtomicBool = AtomicBool::new(false);
+
 pub(crate) type MetadataInner = Mutex<Option<UtocMetadata>>;
 
 #[derive(Debug)]
@@ -23,7 +30,7 @@ impl MetadataState {
     }
 
     pub(crate) fn instance() -> MutexGuard<'static, Option<UtocMetadata>> {
-        let mut guard = UTOC_METADATA.lock().unwrap();
+        let mut guard = UTOC_METADATA.lock().unwrap_or_else(|e| e.into_inner());
         if guard.is_none() {
             *guard = Some(UtocMetadata::default());
         }
@@ -44,7 +51,12 @@ impl DerefMut for MetadataState {
     }
 }
 
-pub struct MetadataAdapter;
+pub struct MetadataAdapter {
+    /// Whether heuristic imports are logged and added to the build report. This is only set while
+    /// building, so that looking up a store entry through the API doesn't report the package again
+    pub report_imports: bool
+}
+
 impl MetadataProvider for MetadataAdapter {
     fn check_v2_import(&self, package_id: FPackageId) -> Option<StoreEntry> {
         MetadataState::instance().as_ref().unwrap()
@@ -61,9 +73,29 @@ impl MetadataProvider for MetadataAdapter {
         let metadata = MetadataState::instance();
         store_entry.imported_packages = match metadata.as_ref().unwrap().get_import_type(package_id) {
             UtocMetaImportType::GraphPackageUnvalidated => LegacyImportIdResolver::from_graph_packages_unvalidated(&package_dependencies),
-            UtocMetaImportType::GraphPackageValidated => LegacyImportIdResolver::from_graph_packages_validated(reader, &package_header, &package_dependencies),
+            UtocMetaImportType::GraphPackageValidated => LegacyImportIdResolver::from_graph_packages_validated(reader, &package_header, package_id, &package_dependencies),
             UtocMetaImportType::ManualV1 => LegacyImportIdResolver::from_metadata_v1(metadata.as_ref().unwrap(), package_id),
             UtocMetaImportType::ManualV2 => unreachable!()
         }
     }
-}
\ No newline at end of file
+    fn allow_heuristic_imports(&self) -> bool {
+        HEURISTIC_IMPORTS.load(Ordering::Relaxed)
+    }
+    fn report_heuristic_imports(&self, package_id: FPackageId, imports: &HeuristicImports) {
+        if !self.report_imports {
+            return;
+        }
+        match imports.confidence {
+            ImportConfidence::Exact => {
+                log!(Debug, "Package {:X} has no metadata, imports {:?} were resolved from it's name map",
+                    package_id.0, imports.package_names);
+            },
+            ImportConfidence::Probable => {
+                log!(Warning, "Package {:X} has no metadata, imports {:?} were guessed from it's name map and may be wrong if it also has soft references. Use the UTOC Extractor to generate metadata for it",
+                    package_id.0, imports.package_names);
+            }
+        }
+        let report = HeuristicImportReport::new(package_id, imports);
+        BuildReport::with(|r| r.heuristic_imports.push(report));
+    }
+}
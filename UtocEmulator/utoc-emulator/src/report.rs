@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, TryLockError};
+use std::time::Instant;
+use retoc::container_header::StoreEntry;
+use retoc::FPackageId;
+use serde::Serialize;
+use utoc_lib::store::HeuristicImports;
+use crate::GenericResult;
+use crate::logger::LogLevel;
+
+/// Set when a report has been requested, so nothing is recorded unless it's going to be written
+pub static BUILD_REPORT: Mutex<Option<ReportState>> = Mutex::new(None);
+
+/// Warnings logged while BUILD_REPORT was locked (e.g from inside BuildReport::with), which are added
+/// to the report the next time it's unlocked
+static PENDING_WARNINGS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+#[derive(Debug)]
+pub struct ReportState {
+    path: PathBuf,
+    report: BuildReport
+}
+
+/// A summary of what IoStoreFactory::build produced, written as JSON so that it can be attached to
+/// bug reports in place of a dump of the emulated container
+#[derive(Debug, Default, Serialize)]
+pub struct BuildReport {
+    pub emulator_version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_profile: Option<String>,
+    pub engine_version: Option<String>,
+    pub container_header_version: Option<String>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub source_folders: Vec<SourceFolderReport>,
+    pub assets: Vec<AssetReport>,
+    /// Packages that were built without metadata by guessing their imports (UE 5.0 - 5.2)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub heuristic_imports: Vec<HeuristicImportReport>,
+    pub warnings: Vec<String>,
+    pub timings: Vec<TimingReport>
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceFolderReport {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mount: Option<String>
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssetReport {
+    /// None for raw chunks, since they aren't in the directory index
+    pub asset_path: Option<String>,
+    pub os_path: String,
+    pub source_folder: Option<String>,
+    pub package_id: String,
+    pub chunk_type: String,
+    pub size: u64,
+    pub ucas_offset: u64,
+    /// How the package's imports were resolved. None if the imports came from the package itself
+    /// (UE 5.3+ imported package names)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub import_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store_entry: Option<StoreEntryReport>
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoreEntryReport {
+    pub export_bundles_size: u64,
+    pub export_count: i32,
+    pub export_bundle_count: i32,
+    pub imported_packages: usize
+}
+
+impl From<&StoreEntry> for StoreEntryReport {
+    fn from(value: &StoreEntry) -> Self {
+        Self {
+            export_bundles_size: value.export_bundles_size,
+            export_count: value.export_count,
+            export_bundle_count: value.export_bundle_count,
+            imported_packages: value.imported_packages.len()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeuristicImportReport {
+    pub package_id: String,
+    pub confidence: String,
+    pub imported_packages: Vec<String>,
+    pub export_bundle_count: i32
+}
+
+impl HeuristicImportReport {
+    pub fn new(package_id: FPackageId, imports: &HeuristicImports) -> Self {
+        Self {
+            package_id: format!("0x{:016X}", package_id.0),
+            confidence: format!("{:?}", imports.confidence),
+            imported_packages: imports.package_names.clone(),
+            export_bundle_count: imports.export_bundle_count
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimingReport {
+    pub name: String,
+    pub milliseconds: f64
+}
+
+impl AssetReport {
+    pub fn new(asset_path: Option<&str>, os_path: &Path, package_id: FPackageId,
+        chunk_type: impl std::fmt::Debug, size: u64, ucas_offset: u64) -> Self {
+        Self {
+            asset_path: asset_path.map(|v| v.to_owned()),
+            os_path: os_path.to_str().unwrap().to_owned(),
+            source_folder: None,
+            package_id: format!("0x{:016X}", package_id.0),
+            chunk_type: format!("{:?}", chunk_type),
+            size,
+            ucas_offset,
+            import_type: None,
+            store_entry: None
+        }
+    }
+}
+
+impl BuildReport {
+    /// Start recording a report, which is written to path once the container has been built.
+    /// Anything logged before this is called won't be included
+    pub(crate) fn enable(path: PathBuf) {
+        *BUILD_REPORT.lock().unwrap_or_else(|e| e.into_inner()) = Some(ReportState {
+            path,
+            report: BuildReport { emulator_version: env!("CARGO_PKG_VERSION"), ..Default::default() }
+        });
+    }
+
+    /// Run f on the report if one is being recorded. Anything logged from inside f is added to the
+    /// report after f returns
+    pub(crate) fn with<F: FnOnce(&mut BuildReport)>(f: F) {
+        if let Some(state) = BUILD_REPORT.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            state.report.take_pending_warnings();
+            f(&mut state.report);
+        }
+    }
+
+    fn take_pending_warnings(&mut self) {
+        self.warnings.append(&mut PENDING_WARNINGS.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    /// Called by the logger, which may be running inside of BuildReport::with. The report is never
+    /// waited on here, since the lock may already be held further up this thread
+    pub(crate) fn record_log(level: LogLevel, text: &str) {
+        if level < LogLevel::Warning {
+            return;
+        }
+        let warning = format!("[{:?}] {}", level, text);
+        match BUILD_REPORT.try_lock() {
+            Ok(mut guard) => if let Some(state) = guard.as_mut() { state.report.warnings.push(warning); },
+            Err(TryLockError::Poisoned(e)) => if let Some(state) = e.into_inner().as_mut() { state.report.warnings.push(warning); },
+            Err(TryLockError::WouldBlock) => PENDING_WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).push(warning)
+        }
+    }
+
+    pub(crate) fn record_time(name: String, start: Instant) {
+        let milliseconds = start.elapsed().as_secs_f64() * 1000.0;
+        Self::with(|r| r.timings.push(TimingReport { name, milliseconds }));
+    }
+
+    /// Fill in each asset's source folder from the folders it was collected from. The longest match
+    /// is used since mods can be nested inside of each other's folders
+    fn resolve_source_folders(&mut self) {
+        for asset in &mut self.assets {
+            asset.source_folder = self.source_folders.iter()
+                .filter(|f| Path::new(&asset.os_path).starts_with(&f.path))
+                .max_by_key(|f| f.path.len())
+                .map(|f| f.path.clone());
+        }
+    }
+
+    /// Write the report for the current build. Recording continues afterwards, with the assets from
+    /// this build cleared so that a rebuild doesn't list them twice
+    pub(crate) fn save(result: &GenericResult<()>) -> GenericResult<()> {
+        let mut guard = BUILD_REPORT.lock().unwrap_or_else(|e| e.into_inner());
+        let state = match guard.as_mut() {
+            Some(v) => v,
+            None => return Ok(())
+        };
+        state.report.take_pending_warnings();
+        state.report.success = result.is_ok();
+        state.report.error = result.as_ref().err().map(|e| e.to_string());
+        state.report.resolve_source_folders();
+        let writer = BufWriter::new(File::create(state.path.as_path())?);
+        serde_json::to_writer_pretty(writer, &state.report)?;
+        state.report.assets.clear();
+        state.report.heuristic_imports.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BuildReport, BUILD_REPORT};
+
+    #[test]
+    fn log_inside_with() {
+        BuildReport::enable(std::env::temp_dir().join("utoc-emulator-report-test.json"));
+        // This would deadlock if the logger waited on the report
+        BuildReport::with(|_| { crate::log!(Warning, "Logged while the report is locked"); });
+        let mut found = false;
+        BuildReport::with(|r| found = r.warnings.iter().any(|w| w.ends_with("Logged while the report is locked")));
+        *BUILD_REPORT.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        assert!(found);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufWriter, Read, Seek};
 use std::ops::{Deref, DerefMut};
@@ -10,20 +11,22 @@ use egui_extras::{Column, TableBuilder};
 use ini::Ini;
 use retoc::container_header::{EIoContainerHeaderVersion, StoreEntry};
 use retoc::FPackageId;
-use retoc::ser::{WriteExt, Writeable};
+use retoc::ser::WriteExt;
 use retoc::version::EngineVersion;
 use retoc::zen::{ExternalPackageDependency, FZenPackageSummary};
 #[cfg(not(target_os = "windows"))]
 use rfd::FileDialog;
-use walkdir::WalkDir;
 #[cfg(target_os = "windows")]
 use wfd::{DialogParams, FOS_PICKFOLDERS};
 use utoc_lib::assets::{convert_to_package_id, AssetEntry, UASSETMETA_EXTENSION, UTOCMETA};
-use utoc_lib::metadata::{UtocMetaImportType, UtocMetadata};
+use utoc_lib::metadata::{UassetMetadata, UtocMetaHeader, UtocMetaImportType, UtocMetadata};
+use utoc_lib::profiles::GameProfile;
 use utoc_lib::store::{LegacyImportIdResolver, MetadataProvider, StoreEntryBuilder, StoreEntryBuilderOld, StoreEntryBuilderNew, os_file_size};
 use crate::cli::Progress;
-use crate::common::{get_default_directory, set_default_directory, try_get_user_config, ActionInfo, AssetMetadata, FilePicker, FilterByAsset, UIComponent};
-use crate::GenericResult;
+use crate::container::GameStoreEntries;
+use crate::common::{get_default_directory, set_default_directory, ActionInfo, AssetMetadata, FilePicker, UIComponent};
+use crate::metadata::ModMetadata;
+use crate::{GenericResult, TOOL_VERSION};
 use crate::gui::AppAction;
 
 pub struct ConvertAction {
@@ -86,31 +89,16 @@ impl ConvertAction {
     pub fn select_mod_folder(&mut self) -> GenericResult<()> {
         self.asset_list.clear();
         self.current_format = None;
-        self.asset_list = WalkDir::new(self.input.get_path()).into_iter()
-            .filter_map(|d| FilterByAsset::filter_by_asset_path(self.input.get_path(), d)).collect();
-        // Metadata check
-        let has_toc_meta = std::fs::read_dir(self.input.get_path())?
-            .find(FilterByAsset::check_utocmeta).is_some();
-        let asset_meta: Vec<_> = self.asset_list.iter().filter(|v| {
-            std::fs::exists(self.input.get_path().join(v).with_extension(UASSETMETA_EXTENSION)).unwrap()
-        }).collect();
-        let no_meta = !has_toc_meta && asset_meta.is_empty();
-        if no_meta && self.engine_version < EngineVersion::UE5_3 {
+        let mod_meta = ModMetadata::new(self.input.get_path())?;
+        self.asset_list = mod_meta.assets;
+        if mod_meta.format == AssetMetadata::None && self.engine_version < EngineVersion::UE5_3 {
             return Err(anyhow!("No asset metadata exists in this mod.").into_boxed_dyn_error());
         }
-        let both_meta = has_toc_meta && !asset_meta.is_empty();
-        if both_meta {
-            return Err(anyhow!("Expected the mod to only have one type of asset metadata.").into_boxed_dyn_error());
-        }
-        if !asset_meta.is_empty() && self.asset_list.len() != asset_meta.len() {
-            return Err(anyhow!("Expected every asset to have an associated .uassetmeta.").into_boxed_dyn_error());
-        }
-        // Setup loaded state
-        if has_toc_meta {
-            self.current_format = Some(AssetMetadata::Table);
-        } else if !asset_meta.is_empty() {
-            self.current_format = Some(AssetMetadata::PerAsset);
-        }
+        mod_meta.check_complete()?;
+        self.current_format = match mod_meta.format {
+            AssetMetadata::None => None,
+            format => Some(format)
+        };
         self.check_convert_type_same();
         Ok(())
     }
@@ -151,7 +139,8 @@ impl ConvertAction {
             self.current_format.unwrap(),
             self.convert_to,
             self.asset_list.as_slice(),
-            self.engine_version
+            self.engine_version,
+            None
         )?;
         self.current_format = Some(match self.convert_to {
             AssetMetadata::None => AssetMetadata::None,
@@ -161,43 +150,14 @@ impl ConvertAction {
         Ok(())
     }
 
-    fn adjust_settings_by_user_config(&mut self) -> Result<(), ActionInfo> {
+    fn adjust_settings_by_profile(&mut self) -> Result<(), ActionInfo> {
         if let Err(e) = self.select_mod_folder() {
             self.asset_list.clear();
             return Err(ActionInfo::error(format!("Can not use folder: {}", e.to_string())));
         }
-        let conf = try_get_user_config();
-        if let Err(e) = &conf {
-            return Err(ActionInfo::info(
-                format!("Could not open config.ini: {}", e.to_string())));
-        }
-        let conf = conf.unwrap();
-        let versions = conf.section(Some("ProjectVersions"));
-        if versions.is_none() {
-            return Err(ActionInfo::info(
-                "Section \"ProjectVersions\" is missing from your config.ini!".to_string()));
-        }
-        let versions = versions.unwrap();
         if let Some(game_name) = self.get_game_name_from_folder() &&
-            let Some(value) = versions.get(game_name.as_str()) {
-            match match value {
-                "UE4_25" => Some(EngineVersion::UE4_25),
-                "UE4_26" => Some(EngineVersion::UE4_26),
-                "UE4_27" => Some(EngineVersion::UE4_27),
-                "UE5_0" => Some(EngineVersion::UE5_0),
-                "UE5_1" => Some(EngineVersion::UE5_1),
-                "UE5_2" => Some(EngineVersion::UE5_2),
-                "UE5_3" => Some(EngineVersion::UE5_3),
-                "UE5_4" => Some(EngineVersion::UE5_4),
-                "UE5_5" => Some(EngineVersion::UE5_5),
-                "UE5_6" => Some(EngineVersion::UE5_6),
-                "UE5_7" => Some(EngineVersion::UE5_7),
-                _ => None
-            } {
-                Some(v) => self.engine_version = v,
-                None => return Err(ActionInfo::info(
-                    format!("Engine version \"{}\" does not exist.", value)))
-            };
+            let Some(profile) = GameProfile::find_by_root_folder(&game_name) {
+            self.engine_version = profile.engine_version;
         }
         Ok(())
     }
@@ -210,7 +170,7 @@ impl AppAction for ConvertAction {
                 .color(info.color));
         }
         if self.input.ui(ui) {
-            if let Err(e) = self.adjust_settings_by_user_config() {
+            if let Err(e) = self.adjust_settings_by_profile() {
                 self.info = Some(e);
             }
         }
@@ -298,7 +258,7 @@ impl MetadataProvider for ConvertMetadata {
         package_dependencies: &[ExternalPackageDependency]) {
         store_entry.imported_packages = match self.0.get_import_type(package_id) {
             UtocMetaImportType::GraphPackageUnvalidated => LegacyImportIdResolver::from_graph_packages_unvalidated(&package_dependencies),
-            UtocMetaImportType::GraphPackageValidated => LegacyImportIdResolver::from_graph_packages_validated(reader, &package_header, &package_dependencies),
+            UtocMetaImportType::GraphPackageValidated => LegacyImportIdResolver::from_graph_packages_validated(reader, &package_header, package_id, &package_dependencies),
             UtocMetaImportType::ManualV1 => LegacyImportIdResolver::from_metadata_v1(&self.0, package_id),
             UtocMetaImportType::ManualV2 => unreachable!()
         }
@@ -348,12 +308,23 @@ impl ConvertExecutor {
         Ok(())
     }
 
+    /// Package ids without the virtual path -> package ids with it, for re-keying existing metadata
+    fn get_mounted_ids(path: &Path, assets: &[PathBuf], vpath: Option<&PathBuf>) -> HashMap<FPackageId, FPackageId> {
+        match vpath {
+            Some(_) => assets.iter().map(|a| (
+                convert_to_package_id(path.join(a), path, None),
+                convert_to_package_id(path.join(a), path, vpath))).collect(),
+            None => HashMap::new()
+        }
+    }
+
     fn convert_per_asset(
         path: PathBuf,
         bar: Progress,
         from: AssetMetadata,
         assets: &[PathBuf],
-        version: EngineVersion
+        version: EngineVersion,
+        vpath: Option<&PathBuf>
     ) -> GenericResult<()> {
         let mut metadata = ConvertMetadata::default();
         match from {
@@ -362,14 +333,15 @@ impl ConvertExecutor {
                 metadata.add_from_utocmeta(
                     std::fs::read(toc_path.as_path())?.as_ref(),
                     version)?;
+                metadata.0 = std::mem::take(&mut metadata.0).rekey(&Self::get_mounted_ids(path.as_path(), assets, vpath));
                 std::fs::remove_file(toc_path.as_path())?;
                 for asset in assets {
                     let meta_path = path.join(asset).with_extension(UASSETMETA_EXTENSION);
-                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), None);
+                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), vpath);
                     match metadata.get_manual_v2_import(package_id) {
                         Some(v) => {
                             let mut writer = File::create(meta_path.as_path())?;
-                            writer.ser(&v)?;
+                            writer.ser(&UassetMetadata::new(version.container_header_version(), package_id, v))?;
                         },
                         None => {
                             println!("{}: Could not get \"{}\" from utocmeta", console::style("Error:").red(), asset.to_str().unwrap());
@@ -385,19 +357,32 @@ impl ConvertExecutor {
                     let asset_path = path.join(asset);
                     let file_size = os_file_size(&asset_path.metadata()?);
                     let asset_entry = AssetEntry::new(asset_path, file_size);
-                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), None);
+                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), vpath);
                     let store = match version.container_header_version() {
                         EIoContainerHeaderVersion::Initial =>
                             StoreEntryBuilderOld::rebuild_store_entry(&asset_entry, package_id, version.container_header_version(), &metadata)?,
                         _ => StoreEntryBuilderNew::rebuild_store_entry(&asset_entry, package_id, version.container_header_version(), &metadata)?
                     };
                     let mut meta_file = File::create(meta_path.as_path())?;
-                    store.ser(&mut meta_file)?;
+                    meta_file.ser(&UassetMetadata::new(version.container_header_version(), package_id, store))?;
                     bar.set_message(asset.to_str().unwrap().to_owned());
                     bar.set_position(bar.position() + 1);
                 }
             },
-            AssetMetadata::PerAsset => unreachable!()
+            // Only used to re-key metadata for a virtual path
+            AssetMetadata::PerAsset => {
+                for asset in assets {
+                    let meta_path = path.join(asset).with_extension(UASSETMETA_EXTENSION);
+                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), vpath);
+                    let unmounted = convert_to_package_id(path.join(asset), path.as_path(), None);
+                    metadata.add_from_uassetmeta(package_id, &[unmounted], meta_path.as_ref(), version)?;
+                    let store = metadata.get_manual_v2_import(package_id).unwrap();
+                    let mut meta_file = File::create(meta_path.as_path())?;
+                    meta_file.ser(&UassetMetadata::new(version.container_header_version(), package_id, store))?;
+                    bar.set_message(asset.to_str().unwrap().to_owned());
+                    bar.set_position(bar.position() + 1);
+                }
+            }
         }
         Ok(())
     }
@@ -407,7 +392,8 @@ impl ConvertExecutor {
         bar: Progress,
         from: AssetMetadata,
         assets: &[PathBuf],
-        version: EngineVersion
+        version: EngineVersion,
+        vpath: Option<&PathBuf>
     ) -> GenericResult<()> {
         let mut metadata = ConvertMetadata::default();
         let toc_path = path.join(UTOCMETA);
@@ -415,8 +401,9 @@ impl ConvertExecutor {
             AssetMetadata::PerAsset => {
                 for asset in assets {
                     let meta_path = path.join(asset).with_extension(UASSETMETA_EXTENSION);
-                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), None);
-                    metadata.add_from_uassetmeta(package_id, meta_path.as_ref())?;
+                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), vpath);
+                    let unmounted = convert_to_package_id(path.join(asset), path.as_path(), None);
+                    metadata.add_from_uassetmeta(package_id, &[unmounted], meta_path.as_ref(), version)?;
                     std::fs::remove_file(meta_path.as_path())?;
                     bar.set_message(asset.to_str().unwrap().to_owned());
                     bar.set_position(bar.position() + 1);
@@ -427,7 +414,7 @@ impl ConvertExecutor {
                     let asset_path = path.join(asset);
                     let file_size = os_file_size(&asset_path.metadata()?);
                     let asset_entry = AssetEntry::new(asset_path, file_size);
-                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), None);
+                    let package_id = convert_to_package_id(path.join(asset), path.as_path(), vpath);
                     let store = match version.container_header_version() {
                         EIoContainerHeaderVersion::Initial =>
                             StoreEntryBuilderOld::rebuild_store_entry(&asset_entry, package_id, version.container_header_version(), &metadata)?,
@@ -438,27 +425,151 @@ impl ConvertExecutor {
                     bar.set_position(bar.position() + 1);
                 }
             },
-            AssetMetadata::Table => unreachable!()
+            // Only used to re-key metadata for a virtual path
+            AssetMetadata::Table => {
+                metadata.add_from_utocmeta(std::fs::read(toc_path.as_path())?.as_ref(), version)?;
+                metadata.0 = std::mem::take(&mut metadata.0).rekey(&Self::get_mounted_ids(path.as_path(), assets, vpath));
+                bar.set_position(assets.len() as u64);
+            }
         }
         let mut writer = BufWriter::new(File::create(toc_path)?);
-        metadata.serialize(&mut writer, version.container_header_version())?;
+        metadata.serialize(&mut writer, &UtocMetaHeader::new(version.container_header_version(), Some(version), TOOL_VERSION))?;
         Ok(())
     }
 
+    /// Copies the store entry that the game has for each asset into the selected metadata format.
+    /// Returns the assets that don't exist in the game, which still need metadata derived from the asset.
+    pub fn convert_from_game<P: AsRef<Path>>(
+        input: P,
+        fmt_to: AssetMetadata,
+        assets: &[PathBuf],
+        version: EngineVersion,
+        game: &GameStoreEntries
+    ) -> GenericResult<Vec<PathBuf>> {
+        if fmt_to == AssetMetadata::None {
+            return Err(anyhow!("Store entries from the game can't be copied without a metadata format to write them to").into_boxed_dyn_error());
+        }
+        let path = input.as_ref().to_owned();
+        let bar = Progress::new(assets.len() as u64)?;
+        let mut metadata = ConvertMetadata::default();
+        let mut new_assets = vec![];
+        for asset in assets {
+            let package_id = convert_to_package_id(path.join(asset), path.as_path(), None);
+            match game.get(package_id) {
+                Some(store) => match fmt_to {
+                    AssetMetadata::PerAsset => {
+                        let meta_path = path.join(asset).with_extension(UASSETMETA_EXTENSION);
+                        let mut meta_file = File::create(meta_path.as_path())?;
+                        meta_file.ser(&UassetMetadata::new(version.container_header_version(), package_id, store.clone()))?;
+                    },
+                    AssetMetadata::Table => metadata.add_from_store_entry(package_id, store.clone())?,
+                    AssetMetadata::None => {}
+                },
+                None => new_assets.push(asset.clone())
+            }
+            bar.set_message(asset.to_str().unwrap().to_owned());
+            bar.set_position(bar.position() + 1);
+        }
+        if fmt_to == AssetMetadata::Table {
+            let mut writer = BufWriter::new(File::create(path.join(UTOCMETA))?);
+            metadata.serialize(&mut writer, &UtocMetaHeader::new(version.container_header_version(), Some(version), TOOL_VERSION))?;
+        }
+        Ok(new_assets)
+    }
+
+    /// Turns the manual import lists from an Essentials 1.x .utocmeta into full store entries by
+    /// parsing each loose asset with it's imports overridden by the list. Every other section of the
+    /// .utocmeta is kept as it is. Returns the number of assets that were upgraded
+    pub fn upgrade_utocmeta<P: AsRef<Path>>(
+        input: P,
+        assets: &[PathBuf],
+        version: EngineVersion
+    ) -> GenericResult<usize> {
+        let path = input.as_ref().to_owned();
+        let toc_path = path.join(UTOCMETA);
+        let mut metadata = ConvertMetadata::default();
+        metadata.add_from_utocmeta(std::fs::read(toc_path.as_path())?.as_ref(), version)?;
+        let bar = Progress::new(assets.len() as u64)?;
+        let mut upgraded = vec![];
+        for asset in assets {
+            let package_id = convert_to_package_id(path.join(asset), path.as_path(), None);
+            if metadata.get_import_type(package_id) == UtocMetaImportType::ManualV1 {
+                let asset_path = path.join(asset);
+                let file_size = os_file_size(&asset_path.metadata()?);
+                let asset_entry = AssetEntry::new(asset_path, file_size);
+                let store = match version.container_header_version() {
+                    EIoContainerHeaderVersion::Initial =>
+                        StoreEntryBuilderOld::rebuild_store_entry(&asset_entry, package_id, version.container_header_version(), &metadata)?,
+                    _ => StoreEntryBuilderNew::rebuild_store_entry(&asset_entry, package_id, version.container_header_version(), &metadata)?
+                };
+                upgraded.push((package_id, store));
+            }
+            bar.set_message(asset.to_str().unwrap().to_owned());
+            bar.set_position(bar.position() + 1);
+        }
+        let count = upgraded.len();
+        for (package_id, store) in upgraded {
+            metadata.remove_manual_v1_import(package_id);
+            metadata.add_from_store_entry(package_id, store)?;
+        }
+        for missing in metadata.get_manual_v1_assets() {
+            println!("{}: Manual imports for package {:?} were kept since there's no asset for it in this mod", console::style("Warning:").yellow(), missing);
+        }
+        let mut writer = BufWriter::new(File::create(toc_path)?);
+        metadata.serialize(&mut writer, &UtocMetaHeader::new(version.container_header_version(), Some(version), TOOL_VERSION))?;
+        Ok(count)
+    }
 
+    /// vpath is the virtual path that the mod will be mounted at, which package ids are computed with.
+    /// Converting to the same format is allowed with a vpath, to re-key the existing metadata
     pub fn convert<P: AsRef<Path>>(
         input: P,
         fmt_from: AssetMetadata,
         fmt_to: AssetMetadata,
         assets: &[PathBuf],
         version: EngineVersion,
+        vpath: Option<&PathBuf>
     ) -> GenericResult<()> {
         let path = input.as_ref().to_owned();
         let bar = Progress::new(assets.len() as u64)?;
         match fmt_to {
             AssetMetadata::None => Self::convert_none(path, bar, assets),
-            AssetMetadata::PerAsset => Self::convert_per_asset(path, bar, fmt_from, assets, version),
-            AssetMetadata::Table => Self::convert_table(path, bar, fmt_from, assets, version),
+            AssetMetadata::PerAsset => Self::convert_per_asset(path, bar, fmt_from, assets, version, vpath),
+            AssetMetadata::Table => Self::convert_table(path, bar, fmt_from, assets, version, vpath),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use retoc::container_header::StoreEntry;
+    use retoc::version::EngineVersion;
+    use utoc_lib::assets::{convert_to_package_id, UTOCMETA};
+    use utoc_lib::metadata::UtocMetadata;
+    use crate::common::AssetMetadata;
+    use crate::container::GameStoreEntries;
+    use crate::GenericResult;
+    use super::ConvertExecutor;
+
+    #[test]
+    fn convert_from_game_table() -> GenericResult<()> {
+        let version = EngineVersion::UE4_27;
+        let path = std::env::temp_dir().join(format!("utoc-extractor-from-game-{}", std::process::id()));
+        let assets = [PathBuf::from("P3R/Content/Found.uasset"), PathBuf::from("P3R/Content/New.uasset")];
+        let found = convert_to_package_id(path.join(&assets[0]), &path, None);
+        let store = StoreEntry { export_count: 3, export_bundle_count: 1, ..Default::default() };
+        let game = GameStoreEntries::new(HashMap::from([(found, store.clone())]));
+        std::fs::create_dir_all(&path)?;
+        let result = ConvertExecutor::convert_from_game(&path, AssetMetadata::Table, &assets, version, &game);
+        let utocmeta = std::fs::read(path.join(UTOCMETA));
+        std::fs::remove_dir_all(&path)?;
+        assert_eq!(result?, vec![assets[1].clone()]);
+        let mut metadata = UtocMetadata::default();
+        metadata.add_from_utocmeta(utocmeta?.as_slice(), version)?;
+        assert_eq!(metadata.get_manual_v2_import(found), Some(store));
+        assert!(ConvertExecutor::convert_from_game(&path, AssetMetadata::None, &assets, version, &game).is_err());
+        Ok(())
+    }
+}
@@ -1,34 +1,40 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader, Cursor};
-use std::path::{Component, PathBuf};
-use std::str::FromStr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use anyhow::{anyhow, Context};
 use chrono::Utc;
+use clap::ValueEnum;
 use eframe::emath::Align;
 use eframe::epaint::Color32;
-use egui::{Button, ComboBox, Layout, RichText, ScrollArea, TextEdit, Ui};
+use egui::{Button, ComboBox, DragValue, Layout, RichText, ScrollArea, TextEdit, Ui};
 use ini::Ini;
-use retoc::{AesKey, Config, EIoChunkType, FGuid, Toc};
-use retoc::container_header::{EIoContainerHeaderVersion, FIoContainerHeader};
+use retoc::{Config, EIoChunkType, Toc};
+use retoc::container_header::FIoContainerHeader;
 use retoc::file_pool::FilePool;
-use retoc::ser::{ReadExt, WriteExt};
+use retoc::ser::ReadExt;
 use retoc::version::EngineVersion;
 #[cfg(target_os = "windows")]
 use wfd::{ DialogParams, FOS_PICKFOLDERS };
 #[cfg(not(target_os = "windows"))]
 use rfd::FileDialog;
-use utoc_lib::metadata::UtocMetadata;
+use utoc_lib::metadata::{UtocMetaHeader, UtocMetadata};
+use utoc_lib::profiles::{GameProfile, MetadataRequirement};
 use crate::cli::Progress;
-use crate::common::{get_root_path, get_default_directory, ActionInfo, AssetMetadata, FilePicker, UIComponent, set_default_directory};
-use crate::GenericResult;
+use crate::container::{self, default_thread_count, extract_chunks, extract_raw_chunks, get_override_profile, ExtractJob};
+use crate::filter::{AssetFilter, ChunkTypeFilter};
+use crate::keys::{KeySource, KeyStore};
+use crate::common::{convert_to_ue_path, get_root_path, get_default_directory, ActionInfo, AssetMetadata, FilePicker, UIComponent, set_default_directory};
+use crate::{GenericResult, TOOL_VERSION};
 use crate::gui::AppAction;
 
 pub struct TocFile {
     name: String,
     path: String,
+    /// From the TOC's chunk id, so the chunk type filter doesn't have to guess it from the extension
+    chunk_type: Option<EIoChunkType>,
     enabled: bool,
 }
 
@@ -80,11 +86,12 @@ pub enum TocEntry {
 }
 
 impl TocEntry {
-    pub fn file(name: String, path: String, ctx: &mut HashSet<String>) -> TocEntry {
+    pub fn file(name: String, path: String, chunk_type: Option<EIoChunkType>, ctx: &mut HashSet<String>) -> TocEntry {
         ctx.insert(path.clone());
         Self::File(TocFile {
             name,
             path,
+            chunk_type,
             enabled: true
         })
     }
@@ -118,6 +125,26 @@ impl TocEntry {
             Self::Directory(d) => d.set_enabled(value, ctx)
         }
     }
+
+    /// Tick every file that matches the filter and untick everything else. A directory stays ticked
+    /// if any of it's children matched. Returns the new enabled state for this node
+    pub fn apply_filter(&mut self, filter: &AssetFilter, ctx: &mut HashSet<String>) -> bool {
+        match self {
+            Self::File(f) => {
+                let value = filter.matches(&f.path, f.chunk_type.as_ref());
+                f.set_enabled(value, ctx);
+                value
+            },
+            Self::Directory(d) => {
+                let mut value = false;
+                for child in &mut d.children {
+                    value |= child.apply_filter(filter, ctx);
+                }
+                d.enabled = value;
+                value
+            }
+        }
+    }
 }
 
 pub struct Debounce {
@@ -157,12 +184,19 @@ pub struct UnpackAction {
     metadata: AssetMetadata,
     can_override_version: bool,
     override_version: EngineVersion,
+    profile: Option<&'static GameProfile>,
     root_name: String,
     output: FilePicker,
     toc: Option<Toc>,
     toc_root: Option<TocEntry>,
     info: Option<ActionInfo>,
     aes_key_reload: Debounce,
+    filter_include: String,
+    filter_exclude: String,
+    filter_extensions: String,
+    filter_chunk_types: Vec<ChunkTypeFilter>,
+    threads: usize,
+    raw_chunks: bool,
 }
 
 const UNPACK_INPUT_TITLE: &'static str = "Select UTOC to unpack";
@@ -242,12 +276,19 @@ impl Default for UnpackAction {
             metadata: AssetMetadata::PerAsset,
             can_override_version: false,
             override_version: EngineVersion::UE5_3,
+            profile: None,
             root_name: "Game".to_string(),
             output: FilePicker::new("Output folder: ", Self::unpack_output),
             toc: None,
             toc_root: None,
             info: None,
-            aes_key_reload: Debounce::new(250)
+            aes_key_reload: Debounce::new(250),
+            filter_include: String::new(),
+            filter_exclude: String::new(),
+            filter_extensions: String::new(),
+            filter_chunk_types: vec![],
+            threads: default_thread_count(),
+            raw_chunks: false,
         }
     }
 }
@@ -260,6 +301,14 @@ impl UnpackAction {
         }
     }
 
+    fn get_override_profile(&self) -> Option<GameProfile> {
+        get_override_profile(self.get_override_version(), self.profile)
+    }
+
+    fn get_root_name(&self) -> &str {
+        self.profile.map_or(self.root_name.as_str(), |p| p.root_folder())
+    }
+
     fn get_aes_key(&self) -> Option<&str> {
         match self.aes_key.is_empty() {
             false => Some(self.aes_key.as_str()),
@@ -268,15 +317,13 @@ impl UnpackAction {
     }
 
     fn create_config(&self) -> GenericResult<Arc<Config>> {
-        let mut config = Config {
-            container_header_version_override: self.get_override_version().map(|v| v.container_header_version()),
-            toc_version_override: self.get_override_version().map(|v| v.toc_version()),
-            ..Default::default()
-        };
-        if let Some(aes) = self.get_aes_key() {
-            config.aes_keys.insert(FGuid::default(), AesKey::from_str(aes)?);
+        // Keys from keys.ini are picked by the container's encryption key GUID, with the key entered
+        // here being used for the default GUID
+        let mut keys = KeyStore::new(None, None, self.get_aes_key(), KeySource::Gui)?;
+        if let Some(profile) = self.profile {
+            keys.add_profile_guids(profile)?;
         }
-        Ok(Arc::new(config))
+        container::create_config(self.input.get_path(), &keys, self.get_override_profile().as_ref())
     }
 
     fn load_utoc(&mut self) -> GenericResult<()> {
@@ -299,7 +346,8 @@ impl UnpackAction {
                 }
             }
             match make_folders {
-                true => Some(vec![self.root_name.as_ref(), "Content"]),
+                // Borrows only the fields, since toc_root is set while this is still in use
+                true => Some(vec![self.profile.map_or(self.root_name.as_str(), |p| p.root_folder()), "Content"]),
                 false => None
                 // false => (vec!["Root"], true),
             }
@@ -326,6 +374,7 @@ impl UnpackAction {
             }
         }
         let mount_point = root_parts.as_ref().map(|v| v.join("/"));
+        let chunk_types: HashMap<_, _> = toc.chunk_id_map.iter().map(|(id, offset)| (*offset, id.get_chunk_type())).collect();
         toc.directory_index.iter_root(|_, path| {
             let mut current = self.toc_root.as_mut().unwrap();
             if let Some(root_parts) = &root_parts {
@@ -361,7 +410,8 @@ impl UnpackAction {
                         Some(mount) => format!("{}/{}", mount, path.join("/")),
                         None => path.join("/")
                     };
-                    d.children.push(TocEntry::file(path.last().unwrap().to_string(), full_path, &mut self.include));
+                    let chunk_type = toc.file_map.get(&path.join("/")).and_then(|offset| chunk_types.get(offset)).copied();
+                    d.children.push(TocEntry::file(path.last().unwrap().to_string(), full_path, chunk_type, &mut self.include));
                 },
                 _ => panic!("Expected a folder")
             }
@@ -371,6 +421,19 @@ impl UnpackAction {
         Ok(())
     }
 
+    fn apply_filter(&mut self) -> GenericResult<()> {
+        let filter = AssetFilter::new(
+            AssetFilter::split_patterns(&self.filter_include).as_slice(),
+            AssetFilter::split_patterns(&self.filter_exclude).as_slice(),
+            AssetFilter::split_patterns(&self.filter_extensions).as_slice(),
+            self.filter_chunk_types.as_slice()
+        )?;
+        if let Some(root) = self.toc_root.as_mut() {
+            root.apply_filter(&filter, &mut self.include);
+        }
+        Ok(())
+    }
+
     fn draw_toc_tree(node: &mut TocEntry, ctx: &mut HashSet<String>, ui: &mut Ui) {
         match node {
             TocEntry::File(f) => {
@@ -412,68 +475,51 @@ impl UnpackAction {
                 .with_context(|| format!("Failed to read chunk {id:?}"))?;
             FIoContainerHeader::deserialize(
                 &mut Cursor::new(&data),
-                self.get_override_version().map(|v| v.container_header_version()))
+                self.get_override_profile().map(|p| p.container_header_version()))
         } else { Err(anyhow!("Could not find the container header in \"{}\"", cas_path.to_str().unwrap())) }?;
         // No metadata warning/error
         let warning = console::Style::new().yellow();
         if self.metadata == AssetMetadata::None {
-            match header.version {
-                EIoContainerHeaderVersion::Initial => {
+            match self.profile.map_or_else(|| MetadataRequirement::for_header_version(header.version), |p| p.metadata_requirement()) {
+                MetadataRequirement::Recommended => {
                     println!("{}: It's recommended to generate asset metadata to prevent issues trying to determine asset dependencies.", warning.apply_to("WARNING"));
                 },
-                v if v < EIoContainerHeaderVersion::NoExportInfo => {
+                MetadataRequirement::Required => {
                     return Err(anyhow!("Metadata is required").into_boxed_dyn_error());
                 },
-                _ => {}
+                MetadataRequirement::Optional => {}
             }
         }
         let output = self.output.get_path();
         let mount_point = toc.directory_index.mount_point.to_string();
-        let content = get_root_path(output.as_path(), &mount_point, &toc, &self.root_name);
-        let mut cas = BufReader::new(File::open(&cas_path)?);
+        let content = get_root_path(output.as_path(), &mount_point, &toc, self.get_root_name());
 
         println!("Metadata type: {:?}", self.metadata);
         println!("Writing into {}", output.to_str().unwrap());
 
-        let assets: Vec<_> = toc.chunk_id_map.iter().filter_map(|(id, offset)|
-            toc.file_map_rev.get(offset).map(|f| (id, f.clone(), *offset))).collect();
-
-        let bar = Progress::new(assets.len() as u64)?;
-
-        let mut toc_meta = UtocMetadata::default();
-        for (id, path, offset) in &assets {
+        let jobs: Vec<_> = toc.chunk_id_map.iter().filter_map(|(id, offset)| {
+            let path = toc.file_map_rev.get(offset)?;
             let os_path = content.join(path);
-            let asset_path = os_path.strip_prefix(output.as_path())?
-                .components().filter_map(|c| match c {
-                Component::Normal(c) => Some(c.to_str().unwrap().to_string()),
-                _ => None }).collect::<Vec<String>>().join("/");
-            if !self.include.contains(&asset_path) {
-                continue;
-            }
-            let store_entry = header.get_store_entry(id.get_package_id());
-            let data = toc.read(&mut cas, *offset as _)?;
-            std::fs::create_dir_all(os_path.parent().unwrap())?;
-            std::fs::write(&os_path, &data)?;
-            if let Some(store_entry) = store_entry {
-                match self.metadata {
-                    AssetMetadata::PerAsset => {
-                        let meta_path = os_path.with_extension("uassetmeta");
-                        let mut meta_file = File::create(meta_path)?;
-                        meta_file.ser(&store_entry)?;
-                    },
-                    AssetMetadata::Table => {
-                        toc_meta.add_from_store_entry(id.get_package_id(), store_entry)?;
-                    },
-                    _ => {}
-                }
+            let asset_path = convert_to_ue_path(os_path.strip_prefix(output.as_path()).ok()?);
+            match self.include.contains(&asset_path) {
+                true => Some(ExtractJob { chunk_id: *id, offset: *offset, os_path, name: path.clone() }),
+                false => None
             }
+        }).collect();
 
-            bar.set_message(path.clone());
-            bar.set_position(bar.position() + 1);
+        let bar = Progress::new(jobs.len() as u64)?;
+        let mut toc_meta = UtocMetadata::default();
+        for (id, store_entry) in extract_chunks(toc, cas_path.as_path(), Some(&header),
+            jobs.as_slice(), self.metadata, self.threads, &bar)? {
+            toc_meta.add_from_store_entry(id, store_entry)?;
+        }
+        if self.raw_chunks {
+            extract_raw_chunks(toc, cas_path.as_path(), output.as_path(), self.threads)?;
         }
         if self.metadata == AssetMetadata::Table {
             let mut meta_file = File::create(output.join(".utocmeta"))?;
-            toc_meta.serialize(&mut meta_file, header.version)?;
+            toc_meta.serialize(&mut meta_file, &UtocMetaHeader::new(
+                header.version, self.get_override_profile().map(|p| p.engine_version), TOOL_VERSION))?;
         }
         Ok(())
     }
@@ -529,11 +575,51 @@ impl AppAction for UnpackAction {
                     ui.selectable_value(&mut self.metadata, AssetMetadata::PerAsset, format!("{}", AssetMetadata::PerAsset));
                     ui.selectable_value(&mut self.metadata, AssetMetadata::Table, format!("{}", AssetMetadata::Table));
                 });
+            ui.label("Game Profile: ");
+            if UIComponent::game_profile_combobox(ui, &mut self.profile) {
+                self.load_utoc_gui();
+            }
             ui.label("Override Engine Version: ");
             ui.checkbox(&mut self.can_override_version, "");
             ui.add_enabled_ui(self.can_override_version, |ui| {
                 UIComponent::engine_version_combobox(ui, &mut self.override_version);
             });
+            ui.label("Threads: ");
+            ui.add(DragValue::new(&mut self.threads).range(1..=256));
+            ui.label("Chunks Without Path: ");
+            ui.checkbox(&mut self.raw_chunks, "");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Chunk Types: ");
+            for chunk_type in ChunkTypeFilter::value_variants() {
+                let mut enabled = self.filter_chunk_types.contains(chunk_type);
+                if ui.checkbox(&mut enabled, format!("{}", chunk_type)).changed() {
+                    match enabled {
+                        true => self.filter_chunk_types.push(*chunk_type),
+                        false => self.filter_chunk_types.retain(|t| t != chunk_type)
+                    }
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Include: ");
+            ui.add(TextEdit::singleline(&mut self.filter_include)
+                .hint_text("Game/Content/**/*.uasset")
+                .desired_width(ui.max_rect().width() / 3.));
+            ui.label("Exclude: ");
+            ui.add(TextEdit::singleline(&mut self.filter_exclude)
+                .desired_width(ui.max_rect().width() / 4.));
+            ui.label("Extensions: ");
+            ui.add(TextEdit::singleline(&mut self.filter_extensions)
+                .hint_text("uasset, ubulk")
+                .desired_width(ui.max_rect().width() / 6.));
+            ui.add_enabled_ui(self.toc_root.is_some(), |ui| {
+                if ui.button("Apply Filter").clicked() {
+                    if let Err(e) = self.apply_filter() {
+                        self.info = Some(ActionInfo::error(format!("Invalid filter: {}", e.to_string())));
+                    }
+                }
+            });
         });
         if let Some(toc) = self.toc.as_ref() {
             ui.label(format!("{} assets ({} selected)", toc.file_map.len(), self.include.len()));
@@ -1,24 +1,25 @@
 use std::fs::File;
-use std::io::{BufReader, Cursor};
+use std::io::{BufWriter, Write};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use clap::Parser;
-use retoc::{AesKey, Config, EIoChunkType, FGuid, Toc};
+use retoc::EIoChunkType;
 use retoc::version::EngineVersion;
-use crate::GenericResult;
-use std::str::FromStr;
-use std::sync::Arc;
-use anyhow::{anyhow, Context};
+use crate::{GenericResult, TOOL_VERSION};
+use anyhow::anyhow;
 use console::{Style, Term};
 use indicatif::{ProgressBar, ProgressStyle};
-use retoc::container_header::{EIoContainerHeaderVersion, FIoContainerHeader};
-use retoc::file_pool::FilePool;
-use retoc::ser::{ReadExt, WriteExt};
-use walkdir::WalkDir;
-use utoc_lib::assets::UASSETMETA_EXTENSION;
-use utoc_lib::metadata::UtocMetadata;
+use utoc_lib::assets::UTOCMETA;
+use utoc_lib::metadata::{UtocMetaHeader, UtocMetadata};
+use utoc_lib::profiles::{GameProfile, MetadataRequirement, DEFAULT_ROOT_FOLDER, GAME_PROFILES};
+use utoc_lib::text::{MetadataDocument, PackageNames};
 use crate::actions::convert::ConvertExecutor;
-use crate::common::{convert_to_ue_path, get_root_path, AssetMetadata, FilterByAsset};
+use crate::container::{self, extract_chunks, extract_raw_chunks, Container, ContainerSet, ExtractJob, GameStoreEntries, get_override_profile};
+use crate::filter::{AssetFilter, ChunkTypeFilter};
+use crate::keys::{KeySource, KeyStore};
+use crate::metadata::{load_metadata_source, MergeOption, ModMetadata, TextFormat};
+use crate::pak::{is_pak, LegacyPak};
+use crate::common::{convert_to_ue_path, get_root_path, AssetMetadata};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -29,21 +30,86 @@ struct Args {
 #[derive(Parser, Debug)]
 struct UnpackArgs {
     // #[arg(short, long)]
-    #[arg(help = "The file path to the .utoc to extract")]
+    #[arg(help = "The file path to the .utoc or .pak to extract")]
     input: String,
-    #[arg(long)]
+    #[arg(long, help = "AES key for containers that use the default encryption key GUID")]
     aes_key: Option<String>,
-    #[arg(short, long, num_args = 1.., value_delimiter = ',', help = "Define a set of paths in the archive to extract. If not specified, everything will be extracted")]
+    #[arg(long, help = "File containing AES keys by encryption key GUID. By default, this is keys.ini next to the executable")]
+    keys: Option<String>,
+    #[arg(long, help = "Only use keys from this game's section in the keys file")]
+    game: Option<String>,
+    #[arg(short, long, num_args = 1.., value_delimiter = ',', help = "Define a set of paths or glob patterns in the archive to extract. If not specified, everything will be extracted")]
     include: Vec<String>,
+    #[arg(short, long, num_args = 1.., value_delimiter = ',', help = "Define a set of paths or glob patterns in the archive to skip")]
+    exclude: Vec<String>,
+    #[arg(long, num_args = 1.., value_delimiter = ',', help = "Only extract files with these extensions")]
+    extension: Vec<String>,
+    #[arg(long, num_args = 1.., value_delimiter = ',', help = "Only extract chunks of these types")]
+    chunk_type: Vec<ChunkTypeFilter>,
     #[arg(short, long)]
     metadata: Option<AssetMetadata>,
     #[arg(long)]
     override_version: Option<EngineVersion>,
-    #[arg(long, help = "Set the name of the root folder. By default, this is \"Game\"")]
+    #[arg(long, help = "Set the name of the root folder. By default, this is the game profile's root folder or \"Game\"")]
     root_name: Option<String>,
+    #[arg(long, value_parser = parse_profile, help = "Take the engine version, root folder name and metadata requirements from this game's profile")]
+    profile: Option<&'static GameProfile>,
     #[arg(short, long)]
     #[arg(help = "The folder to extract into. By default, this will be a in a folder adjacent to the .utoc")]
-    output: Option<String>
+    output: Option<String>,
+    #[arg(short, long, help = "Number of threads to extract with. By default, this is the number of CPU cores")]
+    threads: Option<usize>,
+    #[arg(long, help = "Also extract chunks that don't have a path (shader code, external files...) into a _chunks folder")]
+    raw_chunks: bool
+}
+
+#[derive(Parser, Debug)]
+struct ListArgs {
+    #[arg(help = "The file path to the .utoc or .pak to list the contents of")]
+    input: String,
+    #[arg(long, help = "AES key for containers that use the default encryption key GUID")]
+    aes_key: Option<String>,
+    #[arg(long, help = "File containing AES keys by encryption key GUID. By default, this is keys.ini next to the executable")]
+    keys: Option<String>,
+    #[arg(long, help = "Only use keys from this game's section in the keys file")]
+    game: Option<String>,
+    #[arg(short, long, num_args = 1.., value_delimiter = ',', help = "Define a set of paths or glob patterns in the archive to list. If not specified, everything will be listed")]
+    include: Vec<String>,
+    #[arg(short, long, num_args = 1.., value_delimiter = ',', help = "Define a set of paths or glob patterns in the archive to skip")]
+    exclude: Vec<String>,
+    #[arg(long)]
+    override_version: Option<EngineVersion>,
+    #[arg(long, value_parser = parse_profile, help = "Take the engine version, root folder name and metadata requirements from this game's profile")]
+    profile: Option<&'static GameProfile>,
+}
+
+#[derive(Parser, Debug)]
+struct UnpackAllArgs {
+    #[arg(help = "The game's Paks folder. Every .utoc inside of it will be extracted")]
+    input: String,
+    #[arg(long, help = "AES key for containers that use the default encryption key GUID")]
+    aes_key: Option<String>,
+    #[arg(long, help = "File containing AES keys by encryption key GUID. By default, this is keys.ini next to the executable")]
+    keys: Option<String>,
+    #[arg(long, help = "Only use keys from this game's section in the keys file")]
+    game: Option<String>,
+    #[arg(short, long, num_args = 1.., value_delimiter = ',', help = "Define a set of paths or glob patterns in the archive to extract. If not specified, everything will be extracted")]
+    include: Vec<String>,
+    #[arg(short, long, num_args = 1.., value_delimiter = ',', help = "Define a set of paths or glob patterns in the archive to skip")]
+    exclude: Vec<String>,
+    #[arg(short, long)]
+    metadata: Option<AssetMetadata>,
+    #[arg(long)]
+    override_version: Option<EngineVersion>,
+    #[arg(long, help = "Set the name of the root folder. By default, this is the game profile's root folder or \"Game\"")]
+    root_name: Option<String>,
+    #[arg(long, value_parser = parse_profile, help = "Take the engine version, root folder name and metadata requirements from this game's profile")]
+    profile: Option<&'static GameProfile>,
+    #[arg(short, long)]
+    #[arg(help = "The folder to extract into. By default, this will be a folder named Unpacked adjacent to the Paks folder")]
+    output: Option<String>,
+    #[arg(short, long, help = "Number of threads to extract with. By default, this is the number of CPU cores")]
+    threads: Option<usize>
 }
 
 #[derive(Parser, Debug)]
@@ -53,26 +119,119 @@ struct ConvertArgs {
     input: String,
     #[arg(short, long)]
     metadata: AssetMetadata,
+    #[arg(long, required_unless_present = "profile", conflicts_with = "profile")]
+    version: Option<EngineVersion>,
+    #[arg(long, value_parser = parse_profile, help = "Take the engine version and metadata requirements from this game's profile instead of --version")]
+    profile: Option<&'static GameProfile>,
+    #[arg(long, help = "Copy the store entry for each asset from the game's own containers in this Paks folder")]
+    from_game: Option<String>,
+    #[arg(long, help = "AES key for the game's containers that use the default encryption key GUID, used with --from-game")]
+    aes_key: Option<String>,
+    #[arg(long, help = "File containing AES keys by encryption key GUID. By default, this is keys.ini next to the executable")]
+    keys: Option<String>,
+    #[arg(long, help = "Only use keys from this game's section in the keys file")]
+    game: Option<String>,
+    #[arg(long, help = "Turn the manual import lists in an Essentials 1.x .utocmeta into store entries before converting")]
+    upgrade_utocmeta: bool,
+    #[arg(long, help = "The virtual path that the mod is mounted at, for mods loaded with a virtual mount path")]
+    virtual_path: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct MetadataExportArgs {
+    #[arg(help = "The file path to your mod folder's UnrealEssentials folder")]
+    input: String,
+    #[arg(long)]
+    version: EngineVersion,
+    #[arg(long, help = "Output format. By default, this is taken from the output's extension, or JSON if there's no output")]
+    format: Option<TextFormat>,
+    #[arg(short, long, help = "The file to write to. By default, this will be metadata.json (or .toml) in the input folder")]
+    output: Option<String>,
+    #[arg(long, help = "Also resolve package ids for assets in the game's own containers in this Paks folder")]
+    paks: Option<String>,
+    #[arg(long, help = "AES key for the game's containers that use the default encryption key GUID, used with --paks")]
+    aes_key: Option<String>,
+    #[arg(long, help = "File containing AES keys by encryption key GUID. By default, this is keys.ini next to the executable")]
+    keys: Option<String>,
+    #[arg(long, help = "Only use keys from this game's section in the keys file")]
+    game: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct MetadataImportArgs {
+    #[arg(help = "The JSON or TOML file to import")]
+    input: String,
+    #[arg(short, long, help = "The file path to your mod folder's UnrealEssentials folder")]
+    output: String,
     #[arg(long)]
     version: EngineVersion,
+    #[arg(long, help = "Input format. By default, this is taken from the input's extension")]
+    format: Option<TextFormat>,
+    #[arg(short, long, help = "The type of metadata to write. By default, this is the type that the mod already uses, or table if it has none")]
+    metadata: Option<AssetMetadata>,
+}
+
+#[derive(Parser, Debug)]
+struct MetadataMergeArgs {
+    #[arg(required = true, num_args = 2.., help = "The UnrealEssentials folders or .utocmeta files to merge, in order of priority")]
+    inputs: Vec<String>,
+    #[arg(short, long, help = "The .utocmeta to write. If this is a folder, .utocmeta is written inside of it")]
+    output: String,
+    #[arg(long)]
+    version: EngineVersion,
+    #[arg(long, default_value = "error", help = "What to do when multiple sources have different metadata for the same package")]
+    policy: MergeOption,
+}
+
+#[derive(Parser, Debug)]
+enum MetadataAction {
+    Export(MetadataExportArgs),
+    Import(MetadataImportArgs),
+    Merge(MetadataMergeArgs)
+}
+
+#[derive(Parser, Debug)]
+struct MetadataArgs {
+    #[command(subcommand)]
+    action: MetadataAction
 }
 
 #[derive(Parser, Debug)]
 enum Action {
     Unpack(UnpackArgs),
-    Convert(ConvertArgs)
+    UnpackAll(UnpackAllArgs),
+    List(ListArgs),
+    Convert(ConvertArgs),
+    Metadata(MetadataArgs)
 }
 
-fn create_config(args: &UnpackArgs) -> GenericResult<Arc<Config>> {
-    let mut config = Config {
-        container_header_version_override: args.override_version.map(|v| v.container_header_version()),
-        toc_version_override: args.override_version.map(|v| v.toc_version()),
-        ..Default::default()
-    };
-    if let Some(aes) = args.aes_key.clone() {
-        config.aes_keys.insert(FGuid::default(), AesKey::from_str(&aes)?);
+fn create_key_store(keys: &Option<String>, game: &Option<String>, aes_key: &Option<String>,
+    profile: Option<&GameProfile>) -> GenericResult<KeyStore> {
+    let mut store = KeyStore::new(keys.as_ref().map(|v| Path::new(v.as_str())), game.as_deref(), aes_key.as_deref(), KeySource::Cli)?;
+    if let Some(profile) = profile {
+        store.add_profile_guids(profile)?;
+    }
+    Ok(store)
+}
+
+fn parse_profile(name: &str) -> Result<&'static GameProfile, String> {
+    GameProfile::find_by_name(name).ok_or_else(|| format!("Unknown game profile. Expected one of: {}",
+        GAME_PROFILES.iter().map(|p| p.name).collect::<Vec<_>>().join(", ")))
+}
+
+/// Stop if the archive's packages need metadata and none was selected
+fn check_metadata_requirement(metadata: AssetMetadata, requirement: MetadataRequirement) -> GenericResult<()> {
+    if metadata != AssetMetadata::None {
+        return Ok(());
+    }
+    match requirement {
+        MetadataRequirement::Recommended => {
+            println!("{}: It's recommended to generate asset metadata to prevent issues trying to determine asset dependencies.", Style::new().yellow().apply_to("WARNING"));
+            Ok(())
+        },
+        MetadataRequirement::Required => Err(anyhow!("Metadata is required").into_boxed_dyn_error()),
+        MetadataRequirement::Optional => Ok(())
     }
-    Ok(Arc::new(config))
 }
 
 #[derive(Debug)]
@@ -100,41 +259,59 @@ impl Deref for Progress {
     }
 }
 
+fn get_default_output(input: &Path) -> PathBuf {
+    input.parent().unwrap().join(
+        input.file_stem().map_or("Archive", |v| v.to_str().unwrap()))
+}
+
+fn unpack_pak(args: UnpackArgs, keys: &KeyStore) -> GenericResult<()> {
+    let pak = LegacyPak::open(&args.input, keys)?;
+    if args.metadata.is_some_and(|m| m != AssetMetadata::None) {
+        println!("{}: Assets in .pak archives don't use asset metadata, so none will be written", Style::new().yellow().apply_to("WARNING"));
+    }
+    let input = PathBuf::from(&args.input);
+    let output = args.output.map_or(get_default_output(input.as_path()), |v| PathBuf::from(v));
+    let content = pak.get_root_path(output.as_path());
+    let filter = AssetFilter::new(
+        args.include.as_slice(), args.exclude.as_slice(), args.extension.as_slice(), args.chunk_type.as_slice())?;
+    let files: Vec<_> = pak.files().into_iter()
+        .filter_map(|file| {
+            let os_path = content.join(&file);
+            let path = convert_to_ue_path(os_path.strip_prefix(output.as_path()).unwrap());
+            match filter.matches(&path, None) {
+                true => Some((file, os_path)),
+                false => None
+            }
+        }).collect();
+
+    println!("Writing into {}", output.to_str().unwrap());
+    let bar = Progress::new(files.len() as u64)?;
+    pak.extract(files.as_slice(), args.threads.unwrap_or_else(container::default_thread_count), &bar)?;
+    println!("Wrote {} files", bar.position());
+    Ok(())
+}
+
 fn unpack(args: UnpackArgs) -> GenericResult<()> {
-    let config = create_config(&args)?;
+    let keys = create_key_store(&args.keys, &args.game, &args.aes_key, args.profile)?;
+    if is_pak(&args.input) {
+        return unpack_pak(args, &keys);
+    }
     let metadata = args.metadata.unwrap_or(AssetMetadata::PerAsset);
-    let toc: Toc = BufReader::new(File::open(&args.input)?).de_ctx(config.clone())?;
-    let cas_path = PathBuf::from(&args.input).with_extension("ucas");
-    let cas = FilePool::new(&cas_path, 1)?;
-    let header = if let Some((id, offset)) = toc.chunk_id_map.iter().find(
-        |(id, _)| id.get_chunk_type() == EIoChunkType::ContainerHeader) {
-        let mut file_lock = cas.acquire()?;
-        let data = toc.read(&mut file_lock.file(), *offset)
-            .with_context(|| format!("Failed to read chunk {id:?}"))?;
-        FIoContainerHeader::deserialize(&mut Cursor::new(&data), config.container_header_version_override)
-    } else { Err(anyhow!("Could not find the container header in \"{}\"", cas_path.to_str().unwrap())) }?;
+    let override_profile = get_override_profile(args.override_version, args.profile);
+    let container = Container::open(&args.input, &keys, override_profile.as_ref())?;
+    let header = container.read_header()?;
+    let (toc, cas_path) = (&container.toc, container.cas_path.as_path());
     // No metadata warning/error
-    let warning = Style::new().yellow();
-    if metadata == AssetMetadata::None {
-        match header.version {
-            EIoContainerHeaderVersion::Initial => {
-                println!("{}: It's recommended to generate asset metadata to prevent issues trying to determine asset dependencies.", warning.apply_to("WARNING"));
-            },
-            v if v < EIoContainerHeaderVersion::NoExportInfo => {
-                return Err(anyhow!("Metadata is required").into_boxed_dyn_error());
-            },
-            _ => {}
-        }
-    }
+    check_metadata_requirement(metadata, args.profile.map_or_else(
+        || MetadataRequirement::for_header_version(header.version), |p| p.metadata_requirement()))?;
 
     let input = PathBuf::from(&args.input);
-    let output_default = input.parent().unwrap().join(
-        input.file_stem().map_or("Archive", |v| v.to_str().unwrap()));
-    let output = args.output.map_or(output_default, |v| PathBuf::from(v));
-    let root_folder = args.root_name.as_ref().map_or("Game", |v| v.as_str());
+    let output = args.output.map_or(get_default_output(input.as_path()), |v| PathBuf::from(v));
+    let root_folder = args.root_name.as_deref().unwrap_or_else(|| args.profile.map_or(DEFAULT_ROOT_FOLDER, |p| p.root_folder()));
     let mount_point = toc.directory_index.mount_point.to_string();
-    let content = get_root_path(output.as_path(), &mount_point, &toc, root_folder);
-    let mut cas = BufReader::new(File::open(&cas_path)?);
+    let content = get_root_path(output.as_path(), &mount_point, toc, root_folder);
+    let filter = AssetFilter::new(
+        args.include.as_slice(), args.exclude.as_slice(), args.extension.as_slice(), args.chunk_type.as_slice())?;
 
     println!("Metadata type: {:?}", metadata);
     println!("Writing into {}", output.to_str().unwrap());
@@ -147,85 +324,195 @@ fn unpack(args: UnpackArgs) -> GenericResult<()> {
             let path = content.strip_prefix(output.as_path()).unwrap()
                 .join(entry);
             let path = convert_to_ue_path(path.as_path());
-            match args.include.len() {
-                0 => Some((id, entry.clone(), *offset)),
-                _ => {
-                    args.include.iter().find(|f| path.starts_with(*f))
-                        .map(|_| (id, entry.clone(), *offset))
-                }
+            match filter.matches(&path, Some(&id.get_chunk_type())) {
+                true => Some((id, entry.clone(), *offset)),
+                false => None
             }
     }).collect();
 
-    let bar = Progress::new(assets.len() as u64)?;
+    let jobs: Vec<_> = assets.iter().map(|(id, path, offset)| ExtractJob {
+        chunk_id: **id,
+        offset: *offset,
+        os_path: content.join(path),
+        name: path.clone()
+    }).collect();
+    let bar = Progress::new(jobs.len() as u64)?;
     let mut toc_meta = UtocMetadata::default();
-
-    for (id, path, offset) in &assets {
-        let store_entry = header.get_store_entry(id.get_package_id());
-        let asset_path = content.join(path);
-        let data = toc.read(&mut cas, *offset as _)?;
-        let dir_path = asset_path.parent().unwrap();
-        std::fs::create_dir_all(dir_path)?;
-        std::fs::write(&asset_path, &data)?;
-        if let Some(store_entry) = store_entry {
-            match metadata {
-                AssetMetadata::PerAsset => {
-                    let meta_path = asset_path.with_extension("uassetmeta");
-                    let mut meta_file = File::create(meta_path)?;
-                    meta_file.ser(&store_entry)?;
-                },
-                AssetMetadata::Table => {
-                    toc_meta.add_from_store_entry(id.get_package_id(), store_entry)?;
-                },
-                _ => {}
-            }
-        }
-        bar.set_message(path.clone());
-        bar.set_position(bar.position() + 1);
+    let threads = args.threads.unwrap_or_else(container::default_thread_count);
+    for (id, store_entry) in extract_chunks(toc, cas_path, Some(&header), jobs.as_slice(),
+        metadata, threads, &bar)? {
+        toc_meta.add_from_store_entry(id, store_entry)?;
     }
     if metadata == AssetMetadata::Table {
         let mut meta_file = File::create(output.join(".utocmeta"))?;
-        toc_meta.serialize(&mut meta_file, header.version)?;
+        toc_meta.serialize(&mut meta_file, &UtocMetaHeader::new(
+            header.version, override_profile.map(|p| p.engine_version), TOOL_VERSION))?;
+    }
+    if args.raw_chunks {
+        extract_raw_chunks(toc, cas_path, output.as_path(), threads)?;
     }
     println!("Wrote {} files", bar.position());
     Ok(())
 }
 
+fn list(args: ListArgs) -> GenericResult<()> {
+    let keys = create_key_store(&args.keys, &args.game, &args.aes_key, args.profile)?;
+    let filter = AssetFilter::new(args.include.as_slice(), args.exclude.as_slice(), &[], &[])?;
+    // Listed with the same paths that unpack would write to, relative to the output folder
+    let mut files: Vec<(String, Option<EIoChunkType>)> = if is_pak(&args.input) {
+        let pak = LegacyPak::open(&args.input, &keys)?;
+        let root = pak.get_root_path(PathBuf::new());
+        pak.files().iter().map(|f| (convert_to_ue_path(root.join(f)), None)).collect()
+    } else {
+        let container = Container::open(&args.input, &keys, get_override_profile(args.override_version, args.profile).as_ref())?;
+        let toc = &container.toc;
+        let root_folder = args.profile.map_or(DEFAULT_ROOT_FOLDER, |p| p.root_folder());
+        let root = get_root_path(PathBuf::new(), &toc.directory_index.mount_point.to_string(), toc, root_folder);
+        toc.chunk_id_map.iter()
+            .filter_map(|(id, offset)| toc.file_map_rev.get(offset)
+                .map(|f| (convert_to_ue_path(root.join(f)), Some(id.get_chunk_type()))))
+            .collect()
+    };
+    files.retain(|(f, chunk_type)| filter.matches(f, chunk_type.as_ref()));
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (file, _) in &files {
+        println!("{}", file);
+    }
+    println!("{} files", files.len());
+    Ok(())
+}
+
+const UNPACK_REPORT: &'static str = "UnpackReport.txt";
+
+fn unpack_all(args: UnpackAllArgs) -> GenericResult<()> {
+    let keys = create_key_store(&args.keys, &args.game, &args.aes_key, args.profile)?;
+    let metadata = args.metadata.unwrap_or(AssetMetadata::PerAsset);
+    let override_profile = get_override_profile(args.override_version, args.profile);
+    let mut set = ContainerSet::open_folder(&args.input, &keys, override_profile.as_ref())?;
+    let root_folder = args.root_name.as_deref().unwrap_or_else(|| args.profile.map_or(DEFAULT_ROOT_FOLDER, |p| p.root_folder()));
+    // Paths are resolved relative to the output folder so that containers with different mount
+    // points end up in the same tree
+    set.resolve_files(|c| get_root_path(
+        PathBuf::new(), &c.toc.directory_index.mount_point.to_string(), &c.toc, root_folder));
+    let header_version = set.header_version()
+        .ok_or_else(|| anyhow!("None of the containers in \"{}\" have a container header", &args.input))?;
+    check_metadata_requirement(metadata, args.profile.map_or_else(
+        || MetadataRequirement::for_header_version(header_version), |p| p.metadata_requirement()))?;
+
+    let input = PathBuf::from(&args.input);
+    let output_default = input.parent().unwrap_or(input.as_path()).join("Unpacked");
+    let output = args.output.map_or(output_default, |v| PathBuf::from(v));
+    let filter = AssetFilter::new(args.include.as_slice(), args.exclude.as_slice(), &[], &[])?;
+    let assets: Vec<_> = set.files.iter()
+        .filter(|(path, chunk)| filter.matches(
+            &convert_to_ue_path(path), Some(&chunk.chunk_id.get_chunk_type())))
+        .collect();
+
+    println!("Loaded {} containers", set.containers.len());
+    println!("Metadata type: {:?}", metadata);
+    println!("Writing into {}", output.to_str().unwrap());
+
+    let threads = args.threads.unwrap_or_else(container::default_thread_count);
+    let bar = Progress::new(assets.len() as u64)?;
+    let mut toc_meta = UtocMetadata::default();
+    for (i, container) in set.containers.iter().enumerate() {
+        let jobs: Vec<_> = assets.iter()
+            .filter(|(_, chunk)| chunk.container == i)
+            .map(|(path, chunk)| ExtractJob {
+                chunk_id: chunk.chunk_id,
+                offset: chunk.offset,
+                os_path: output.join(path),
+                name: convert_to_ue_path(path)
+            }).collect();
+        if jobs.is_empty() { continue; }
+        // Metadata always comes from the same container as the asset
+        for (id, store_entry) in extract_chunks(&container.toc, container.cas_path.as_path(),
+            set.headers[i].as_ref(), jobs.as_slice(), metadata, threads, &bar)? {
+            toc_meta.add_from_store_entry(id, store_entry)?;
+        }
+    }
+    let mut report: Vec<u8> = vec![];
+    for (path, chunk) in &assets {
+        write!(report, "{} <- {}", convert_to_ue_path(path), set.containers[chunk.container].get_name())?;
+        if !chunk.overrides.is_empty() {
+            let overrides: Vec<_> = chunk.overrides.iter().map(|i| set.containers[*i].get_name()).collect();
+            write!(report, " (overrides {})", overrides.join(", "))?;
+        }
+        writeln!(report)?;
+    }
+    if metadata == AssetMetadata::Table {
+        let mut meta_file = File::create(output.join(UTOCMETA))?;
+        toc_meta.serialize(&mut meta_file, &UtocMetaHeader::new(
+            header_version, override_profile.map(|p| p.engine_version), TOOL_VERSION))?;
+    }
+    std::fs::write(output.join(UNPACK_REPORT), report)?;
+    println!("Wrote {} files from {} containers", bar.position(), set.containers.len());
+    Ok(())
+}
+
+fn convert_from_game(args: &ConvertArgs, profile: &GameProfile, paks: &str, input: &Path, asset_list: &[PathBuf],
+    current_format: AssetMetadata) -> GenericResult<()> {
+    if args.metadata == AssetMetadata::None {
+        return Err(anyhow!("A metadata format must be selected to copy metadata from the game").into_boxed_dyn_error());
+    }
+    if current_format != AssetMetadata::None {
+        return Err(anyhow!("This mod already has asset metadata ({:?})", current_format).into_boxed_dyn_error());
+    }
+    let keys = create_key_store(&args.keys, &args.game, &args.aes_key, args.profile)?;
+    let game = GameStoreEntries::from_paks_folder(paks, &keys, profile)?;
+    println!("Loaded {} store entries from {}", game.len(), paks);
+    let new_assets = ConvertExecutor::convert_from_game(
+        input,
+        args.metadata,
+        asset_list,
+        profile.engine_version,
+        &game
+    )?;
+    println!("Copied metadata for {} assets", asset_list.len() - new_assets.len());
+    if !new_assets.is_empty() {
+        println!("{}: {} assets are not in the game and still need metadata derived from the asset:",
+            Style::new().yellow().apply_to("WARNING"), new_assets.len());
+        for asset in &new_assets {
+            println!("  {}", asset.to_str().unwrap());
+        }
+    }
+    Ok(())
+}
+
 fn convert(args: ConvertArgs) -> GenericResult<()> {
-    if args.metadata == AssetMetadata::None && args.version < EngineVersion::UE5_3 {
+    // clap makes sure that exactly one of --version and --profile is set
+    let profile = args.profile.copied().unwrap_or_else(|| GameProfile::for_engine(args.version.unwrap()));
+    let needs_metadata = profile.metadata_requirement() != MetadataRequirement::Optional;
+    if args.from_game.is_none() && args.metadata == AssetMetadata::None && needs_metadata {
         return Err(anyhow!("Asset metadata is required for games below UE 5.3!").into_boxed_dyn_error());
     }
     let input = PathBuf::from(&args.input);
-    // ConvertAction::select_mod_folder
-    let asset_list: Vec<_> = WalkDir::new(input.as_path()).into_iter()
-        .filter_map(|d| FilterByAsset::filter_by_asset_path(input.as_path(), d)).collect();
-    // Metadata check
-    let has_toc_meta = std::fs::read_dir(input.as_path())?
-        .find(FilterByAsset::check_utocmeta).is_some();
-    let asset_meta: Vec<_> = asset_list.iter().filter(|v| {
-        std::fs::exists(input.as_path().join(v).with_extension(UASSETMETA_EXTENSION)).unwrap()
-    }).collect();
-    let no_meta = !has_toc_meta && asset_meta.is_empty();
-    if no_meta && args.version < EngineVersion::UE5_3 {
+    let mod_meta = ModMetadata::new(input.as_path())?;
+    mod_meta.check_complete()?;
+    let (asset_list, current_format) = (mod_meta.assets, mod_meta.format);
+    if current_format == AssetMetadata::None && needs_metadata && args.from_game.is_none() {
         return Err(anyhow!("No asset metadata exists in this mod.").into_boxed_dyn_error());
     }
-    let both_meta = has_toc_meta && !asset_meta.is_empty();
-    if both_meta {
-        return Err(anyhow!("Expected the mod to only have one type of asset metadata.").into_boxed_dyn_error());
+    if args.from_game.is_some() && args.virtual_path.is_some() {
+        return Err(anyhow!("--from-game can't be used with --virtual-path, since the game's packages aren't mounted at a virtual path").into_boxed_dyn_error());
     }
-    if !asset_meta.is_empty() && asset_list.len() != asset_meta.len() {
-        return Err(anyhow!("Expected every asset to have an associated .uassetmeta.").into_boxed_dyn_error());
+    if let Some(paks) = args.from_game.as_ref() {
+        return convert_from_game(&args, &profile, paks, input.as_path(), asset_list.as_slice(), current_format);
     }
-    let current_format = if has_toc_meta {
-        AssetMetadata::Table
-    } else if !asset_meta.is_empty() {
-        AssetMetadata::PerAsset
-    } else {
-        AssetMetadata::None
-    };
-    if current_format == AssetMetadata::None && args.version < EngineVersion::UE5_3 {
+    if current_format == AssetMetadata::None && needs_metadata {
         return Err(anyhow!("Cannot convert metadata if there is no existing metadata").into_boxed_dyn_error());
     }
-    if args.metadata == current_format {
+    if args.upgrade_utocmeta {
+        if current_format != AssetMetadata::Table {
+            return Err(anyhow!("--upgrade-utocmeta requires the mod to have a .utocmeta").into_boxed_dyn_error());
+        }
+        let count = ConvertExecutor::upgrade_utocmeta(input.as_path(), asset_list.as_slice(), profile.engine_version)?;
+        println!("Upgraded manual imports for {} assets", count);
+        if args.metadata == current_format && args.virtual_path.is_none() {
+            return Ok(());
+        }
+    }
+    if args.metadata == current_format && (args.virtual_path.is_none() || current_format == AssetMetadata::None) {
         return Err(anyhow!(format!("Asset metadata is already in the format {:?}!", args.metadata)).into_boxed_dyn_error());
     }
     ConvertExecutor::convert(
@@ -233,14 +520,82 @@ fn convert(args: ConvertArgs) -> GenericResult<()> {
         current_format,
         args.metadata,
         asset_list.as_slice(),
-        args.version
+        profile.engine_version,
+        args.virtual_path.map(|v| PathBuf::from(v)).as_ref()
     )?;
     Ok(())
 }
 
+fn metadata_export(args: MetadataExportArgs) -> GenericResult<()> {
+    let mod_meta = ModMetadata::new(&args.input)?;
+    let metadata = mod_meta.load(args.version)?;
+    let mut names = PackageNames::default();
+    mod_meta.add_package_names(&mut names);
+    if let Some(paks) = args.paks.as_ref() {
+        let keys = create_key_store(&args.keys, &args.game, &args.aes_key, None)?;
+        ContainerSet::open_folder(paks, &keys, Some(&GameProfile::for_engine(args.version)))?.add_package_names(&mut names);
+    }
+    let format = args.format.unwrap_or_else(|| args.output.as_ref().map_or(TextFormat::Json, |v| TextFormat::from_path(v)));
+    let output = args.output.map_or_else(
+        || mod_meta.path.join("metadata").with_extension(format.get_extension()), |v| PathBuf::from(v));
+    let document = MetadataDocument::from_metadata(metadata, &names)?;
+    std::fs::write(output.as_path(), format.write(&document)?)?;
+    println!("Exported {} store entries, {} manual import lists and {} validated imports from {:?} metadata into {}",
+        document.store_entries.len(), document.manual_imports.len(), document.validated_imports.len(),
+        mod_meta.format, output.to_str().unwrap());
+    Ok(())
+}
+
+fn metadata_import(args: MetadataImportArgs) -> GenericResult<()> {
+    let format = args.format.unwrap_or_else(|| TextFormat::from_path(&args.input));
+    let document = format.read(&std::fs::read_to_string(&args.input)?)?;
+    let mut mod_meta = ModMetadata::new(&args.output)?;
+    let target = args.metadata.unwrap_or(match mod_meta.format {
+        AssetMetadata::None => AssetMetadata::Table,
+        v => v
+    });
+    if target == AssetMetadata::None {
+        return Err(anyhow!("Imported metadata must be written as either table or per-asset metadata").into_boxed_dyn_error());
+    }
+    mod_meta.save(document.to_metadata()?, target, args.version)?;
+    println!("Imported {} store entries into {:?} metadata", document.store_entries.len(), target);
+    Ok(())
+}
+
+fn metadata_merge(args: MetadataMergeArgs) -> GenericResult<()> {
+    let mut names = PackageNames::default();
+    let mut merged = UtocMetadata::default();
+    for input in &args.inputs {
+        let metadata = load_metadata_source(input, args.version, &mut names)
+            .map_err(|e| anyhow!("Could not load \"{}\": {}", input, e))?;
+        let conflicts = merged.merge(metadata, args.policy.to_policy())
+            .map_err(|e| anyhow!("Could not merge \"{}\": {}", input, e))?;
+        for conflict in conflicts {
+            println!("{}: {} has a different {:?} in \"{}\", using the one from the {} source",
+                Style::new().yellow().apply_to("CONFLICT"), names.get(conflict.package_id), conflict.section, input,
+                if args.policy == MergeOption::First { "first" } else { "last" });
+        }
+    }
+    let mut output = PathBuf::from(&args.output);
+    if output.is_dir() {
+        output = output.join(UTOCMETA);
+    }
+    let mut writer = BufWriter::new(File::create(output.as_path())?);
+    merged.serialize(&mut writer, &UtocMetaHeader::new(args.version.container_header_version(), Some(args.version), TOOL_VERSION))?;
+    println!("Merged {} sources into {}", args.inputs.len(), output.to_str().unwrap());
+    Ok(())
+}
+
 pub(crate) fn execute() -> GenericResult<()> {
     match Args::parse().action {
         Action::Unpack(args) => unpack(args),
-        Action::Convert(args) => convert(args)
+        Action::UnpackAll(args) => unpack_all(args),
+        Action::List(args) => list(args),
+        Action::Convert(args) => convert(args),
+        Action::Metadata(args) => match args.action {
+            MetadataAction::Export(args) => metadata_export(args),
+            MetadataAction::Import(args) => metadata_import(args),
+            MetadataAction::Merge(args) => metadata_merge(args)
+        }
     }
 }
\ No newline at end of file
@@ -12,6 +12,7 @@ use walkdir::DirEntry;
 #[cfg(not(target_os = "windows"))]
 use rfd::FileDialog;
 use utoc_lib::assets::{UASSET_EXTENSION, UMAP_EXTENSION, UTOCMETA};
+use utoc_lib::profiles::{GameProfile, GAME_PROFILES};
 use crate::GenericResult;
 use crate::gui::AppTab;
 
@@ -149,6 +150,22 @@ impl ActionInfo {
 
 pub struct UIComponent;
 impl UIComponent {
+    /// Pick one of the games in GAME_PROFILES, or None to use the defaults for the container
+    pub(crate) fn game_profile_combobox(ui: &mut Ui, profile: &mut Option<&'static GameProfile>) -> bool {
+        let mut changed = false;
+        ComboBox::new("game_profile", "")
+            .selected_text(profile.map_or("None", |p| p.display_name))
+            .show_ui(ui, |ui| {
+                changed |= ui.selectable_label(profile.is_none(), "None").clicked() && profile.take().is_some();
+                for game in &GAME_PROFILES {
+                    if ui.selectable_label(profile.is_some_and(|p| p.id == game.id), game.display_name).clicked() {
+                        changed |= profile.replace(game).is_none_or(|p| p.id != game.id);
+                    }
+                }
+            });
+        changed
+    }
+
     pub(crate) fn engine_version_combobox(ui: &mut Ui, version: &mut EngineVersion) {
         ComboBox::new("engine_version", "")
             .selected_text(format!("{:?}", *version))
@@ -168,18 +185,6 @@ impl UIComponent {
     }
 }
 
-pub fn get_config() -> GenericResult<PathBuf> {
-    let exec = std::env::current_exe()?;
-    let dir = exec.parent().unwrap();
-    let path = dir.join("config.ini");
-    if !std::fs::exists(path.as_path())? {
-        let mut ini = Ini::new();
-        _ = ini.with_section(Some("ProjectVersions"));
-        ini.write_to_file(path.as_path())?;
-    }
-    Ok(path)
-}
-
 
 pub fn get_egui_state() -> GenericResult<PathBuf> {
     let exec = std::env::current_exe()?;
@@ -213,12 +218,6 @@ pub fn set_default_directory<P: AsRef<Path>>(path: Option<P>, ini: Option<&mut I
     }
 }
 
-pub fn try_get_user_config() -> GenericResult<Ini> {
-    let state = get_config()?;
-    let conf = Ini::load_from_file(state)?;
-    Ok(conf)
-}
-
 pub fn convert_to_ue_path<P: AsRef<Path>>(path: P) -> String {
     path.as_ref().components().filter_map(|c| match c {
         Component::Normal(c) => Some(c.to_str().unwrap().to_string()),
@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use anyhow::{anyhow, Context};
+use retoc::{AesKey, Config, EIoChunkType, FIoChunkId, FPackageId, Toc};
+use retoc::container_header::{EIoContainerHeaderVersion, FIoContainerHeader, StoreEntry};
+use retoc::file_pool::FilePool;
+use indicatif::ProgressBar;
+use rayon::{ThreadPoolBuilder, prelude::*};
+use retoc::ser::{ReadExt, WriteExt};
+use retoc::version::EngineVersion;
+use walkdir::WalkDir;
+use utoc_lib::assets::{asset_path_to_package_name, get_asset_path, UASSETMETA_EXTENSION};
+use utoc_lib::metadata::UassetMetadata;
+use utoc_lib::profiles::GameProfile;
+use utoc_lib::text::PackageNames;
+use utoc_lib::chunks::{get_raw_chunk_file_name, get_raw_chunk_type_name, RAW_CHUNK_FOLDER, RAW_CHUNK_MANIFEST};
+use crate::cli::Progress;
+use crate::common::AssetMetadata;
+use crate::keys::KeyStore;
+use crate::GenericResult;
+
+pub(crate) const UTOC_EXTENSION: &'static str = "utoc";
+pub(crate) const UCAS_EXTENSION: &'static str = "ucas";
+
+/// The versions that containers are read with. An engine version override takes priority over the
+/// game profile, otherwise the profile's own TOC and container header versions are used
+pub(crate) fn get_override_profile(override_version: Option<EngineVersion>, profile: Option<&GameProfile>) -> Option<GameProfile> {
+    override_version.map(GameProfile::for_engine).or_else(|| profile.copied())
+}
+
+/// Create the config for reading one container. The AES key is picked by the container's encryption
+/// key GUID
+pub(crate) fn create_config<P: AsRef<Path>>(path: P, keys: &KeyStore, profile: Option<&GameProfile>) -> GenericResult<Arc<Config>> {
+    let mut config = Config {
+        container_header_version_override: profile.map(|p| p.container_header_version()),
+        toc_version_override: profile.map(|p| p.toc_version()),
+        ..Default::default()
+    };
+    if let Some((guid, aes)) = keys.get_for_container(path)? {
+        config.aes_keys.insert(guid.to_guid()?, AesKey::from_str(aes)?);
+    }
+    Ok(Arc::new(config))
+}
+
+/// A .utoc and the path to the .ucas that holds its chunk data
+pub(crate) struct Container {
+    pub(crate) toc: Toc,
+    pub(crate) cas_path: PathBuf,
+    config: Arc<Config>
+}
+
+impl Container {
+    pub(crate) fn open<P: AsRef<Path>>(path: P, keys: &KeyStore, profile: Option<&GameProfile>) -> GenericResult<Self> {
+        let path = path.as_ref();
+        let config = create_config(path, keys, profile)?;
+        let toc: Toc = BufReader::new(File::open(path)?).de_ctx(config.clone())?;
+        let cas_path = path.with_extension(UCAS_EXTENSION);
+        Ok(Self { toc, cas_path, config })
+    }
+
+    pub(crate) fn read_header(&self) -> GenericResult<FIoContainerHeader> {
+        let cas = FilePool::new(&self.cas_path, 1)?;
+        if let Some((id, offset)) = self.toc.chunk_id_map.iter().find(
+            |(id, _)| id.get_chunk_type() == EIoChunkType::ContainerHeader) {
+            let mut file_lock = cas.acquire()?;
+            let data = self.toc.read(&mut file_lock.file(), *offset)
+                .with_context(|| format!("Failed to read chunk {id:?}"))?;
+            Ok(FIoContainerHeader::deserialize(&mut Cursor::new(&data), self.config.container_header_version_override)?)
+        } else {
+            Err(anyhow!("Could not find the container header in \"{}\"", self.cas_path.to_str().unwrap()).into_boxed_dyn_error())
+        }
+    }
+
+    /// Every .utoc inside of a game's Paks folder (including subfolders such as ~mods), ordered from
+    /// the lowest to the highest priority
+    pub(crate) fn find_in_folder<P: AsRef<Path>>(paks: P) -> Vec<PathBuf> {
+        let mut containers: Vec<PathBuf> = WalkDir::new(paks.as_ref()).into_iter()
+            .filter_map(|d| d.ok())
+            .filter(|d| d.path().extension().map_or(false, |e| e == UTOC_EXTENSION))
+            .map(|d| d.into_path())
+            .collect();
+        containers.sort_by(|a, b| Self::get_order(a).cmp(&Self::get_order(b))
+            .then_with(|| a.cmp(b)));
+        containers
+    }
+
+    /// Mount order based on FPakPlatformFile::Mount: patch containers ending in _P get an extra 100
+    /// for each patch version (pakchunk0_P is version 1, pakchunk0_2_P is version 3...) so they override the base
+    /// container. Containers with the same order are mounted in name order
+    pub(crate) fn get_order<P: AsRef<Path>>(path: P) -> u32 {
+        let stem = path.as_ref().file_stem().map_or("", |v| v.to_str().unwrap());
+        match stem.strip_suffix("_P") {
+            Some(base) => {
+                let version = base.rsplit_once('_')
+                    .and_then(|(_, v)| v.parse::<u32>().ok())
+                    .filter(|v| *v >= 1)
+                    .map_or(1, |v| v + 1);
+                100 * version
+            },
+            None => 0
+        }
+    }
+
+    pub(crate) fn get_name(&self) -> &str {
+        self.cas_path.file_stem().map_or("", |v| v.to_str().unwrap())
+    }
+}
+
+/// A chunk that exists in one of the containers inside of a ContainerSet
+#[derive(Debug)]
+pub(crate) struct ResolvedChunk {
+    pub(crate) container: usize,
+    pub(crate) chunk_id: FIoChunkId,
+    pub(crate) offset: u32,
+    /// Lower priority containers that also had a file at this path
+    pub(crate) overrides: Vec<usize>
+}
+
+/// Every container inside of a game's Paks folder, with files at the same path resolved to the
+/// version that the game would load
+pub(crate) struct ContainerSet {
+    pub(crate) containers: Vec<Container>,
+    pub(crate) headers: Vec<Option<FIoContainerHeader>>,
+    /// Keyed by the path inside of the container's directory index, relative to it's content root
+    pub(crate) files: BTreeMap<PathBuf, ResolvedChunk>,
+}
+
+impl ContainerSet {
+    pub(crate) fn open_folder<P: AsRef<Path>>(paks: P, keys: &KeyStore, profile: Option<&GameProfile>) -> GenericResult<Self> {
+        let paths = Container::find_in_folder(paks.as_ref());
+        if paths.is_empty() {
+            return Err(anyhow!("No IO Store containers were found in \"{}\"", paks.as_ref().to_str().unwrap()).into_boxed_dyn_error());
+        }
+        let mut containers = vec![];
+        let mut headers = vec![];
+        for path in &paths {
+            match Container::open(path, keys, profile) {
+                Ok(v) => {
+                    // Global.utoc and other containers with no packages don't have a container header
+                    headers.push(v.read_header().ok());
+                    containers.push(v);
+                },
+                Err(e) => println!("{}: Skipping \"{}\": {}", console::style("WARNING").yellow(), path.to_str().unwrap(), e)
+            }
+        }
+        Ok(Self { containers, headers, files: BTreeMap::new() })
+    }
+
+    /// Resolve each file path to the highest priority container that has it. root_path returns the
+    /// folder that the container's files would be unpacked into, so that containers with different
+    /// mount points still line up
+    pub(crate) fn resolve_files<F>(&mut self, root_path: F)
+    where F: Fn(&Container) -> PathBuf {
+        self.files.clear();
+        for (i, container) in self.containers.iter().enumerate() {
+            let root = root_path(container);
+            for (chunk_id, offset) in &container.toc.chunk_id_map {
+                let Some(entry) = container.toc.file_map_rev.get(offset) else { continue };
+                let path = root.join(entry);
+                let overrides = match self.files.remove(&path) {
+                    Some(mut prev) => {
+                        prev.overrides.push(prev.container);
+                        prev.overrides
+                    },
+                    None => vec![]
+                };
+                self.files.insert(path, ResolvedChunk { container: i, chunk_id: *chunk_id, offset: *offset, overrides });
+            }
+        }
+    }
+
+    pub(crate) fn get_store_entry(&self, chunk: &ResolvedChunk) -> Option<StoreEntry> {
+        self.headers[chunk.container].as_ref()
+            .and_then(|h| h.get_store_entry(chunk.chunk_id.get_package_id()))
+    }
+
+    pub(crate) fn header_version(&self) -> Option<EIoContainerHeaderVersion> {
+        self.headers.iter().find_map(|h| h.as_ref().map(|h| h.version))
+    }
+
+    /// Add the package name for every package in the set. Names are derived from the file's path, so
+    /// they're checked against the chunk's package id to skip any that don't follow the usual layout
+    pub(crate) fn add_package_names(&self, names: &mut PackageNames) {
+        for container in &self.containers {
+            let mount_point = container.toc.directory_index.mount_point.to_string();
+            let Some(mount_point) = mount_point.strip_prefix(utoc_lib::assets::MOUNT_POINT) else { continue };
+            for (chunk_id, offset) in &container.toc.chunk_id_map {
+                if chunk_id.get_chunk_type() != EIoChunkType::ExportBundleData { continue; }
+                let Some(entry) = container.toc.file_map_rev.get(offset) else { continue };
+                let path = format!("{}{}", mount_point, entry);
+                if path.splitn(3, '/').count() < 3 || !path.contains('.') { continue; }
+                let asset_path = get_asset_path(&path, None);
+                names.add_checked(asset_path_to_package_name(&asset_path), chunk_id.get_package_id());
+            }
+        }
+    }
+}
+
+/// A chunk to extract from a container and the path to write it to
+#[derive(Debug)]
+pub(crate) struct ExtractJob {
+    pub(crate) chunk_id: FIoChunkId,
+    pub(crate) offset: u32,
+    pub(crate) os_path: PathBuf,
+    /// Shown in the progress bar
+    pub(crate) name: String,
+}
+
+pub(crate) fn default_thread_count() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Reads, decompresses and writes chunks on a pool of threads, each with it's own handle to the .ucas.
+/// Per asset metadata is written alongside each package while it's extracted. Store entries for table
+/// metadata are returned so they can be added into a UtocMetadata once every thread is done.
+pub(crate) fn extract_chunks(
+    toc: &Toc,
+    cas_path: &Path,
+    header: Option<&FIoContainerHeader>,
+    jobs: &[ExtractJob],
+    metadata: AssetMetadata,
+    threads: usize,
+    bar: &ProgressBar
+) -> GenericResult<Vec<(FPackageId, StoreEntry)>> {
+    let threads = threads.max(1);
+    let cas = FilePool::new(cas_path, threads)?;
+    let pool = ThreadPoolBuilder::new().num_threads(threads).build()?;
+    let entries = pool.install(|| jobs.par_iter().map(|job| -> anyhow::Result<Option<(FPackageId, StoreEntry)>> {
+        let data = {
+            let mut file_lock = cas.acquire()?;
+            toc.read(&mut file_lock.file(), job.offset)
+                .with_context(|| format!("Failed to read chunk {:?}", job.chunk_id))?
+        };
+        std::fs::create_dir_all(job.os_path.parent().unwrap())?;
+        std::fs::write(&job.os_path, &data)?;
+        // Bulk data chunks share their package's id, so metadata is only written once for the package
+        // chunk. Otherwise each of them would write the same .uassetmeta from different threads
+        let store_entry = match job.chunk_id.get_chunk_type() {
+            EIoChunkType::ExportBundleData => header.and_then(|h| h.get_store_entry(job.chunk_id.get_package_id())),
+            _ => None
+        };
+        let result = match (store_entry, metadata) {
+            (Some(store_entry), AssetMetadata::PerAsset) => {
+                let mut meta_file = File::create(job.os_path.with_extension(UASSETMETA_EXTENSION))?;
+                meta_file.ser(&UassetMetadata::new(header.unwrap().version, job.chunk_id.get_package_id(), store_entry))?;
+                None
+            },
+            (Some(store_entry), AssetMetadata::Table) => Some((job.chunk_id.get_package_id(), store_entry)),
+            _ => None
+        };
+        bar.set_message(job.name.clone());
+        bar.inc(1);
+        Ok(result)
+    }).collect::<anyhow::Result<Vec<_>>>())?;
+    Ok(entries.into_iter().flatten().collect())
+}
+
+/// Extract every chunk that doesn't have a path in the directory index into the raw chunk folder,
+/// along with a manifest listing the type and size of each chunk. Returns the number of chunks written
+pub(crate) fn extract_raw_chunks(toc: &Toc, cas_path: &Path, output: &Path, threads: usize) -> GenericResult<usize> {
+    let folder = output.join(RAW_CHUNK_FOLDER);
+    let jobs: Vec<_> = toc.chunk_id_map.iter()
+        .filter(|(_, offset)| !toc.file_map_rev.contains_key(*offset))
+        .map(|(id, offset)| {
+            let name = get_raw_chunk_file_name(id);
+            ExtractJob { chunk_id: *id, offset: *offset, os_path: folder.join(&name), name }
+        }).collect();
+    if jobs.is_empty() {
+        return Ok(0);
+    }
+    println!("Writing {} chunks without a path into {}", jobs.len(), folder.to_str().unwrap());
+    let bar = Progress::new(jobs.len() as u64)?;
+    extract_chunks(toc, cas_path, None, jobs.as_slice(), AssetMetadata::None, threads, &bar)?;
+    let mut manifest: Vec<u8> = vec![];
+    writeln!(manifest, "# file\tchunk id\tchunk index\tchunk type\tsize")?;
+    for job in &jobs {
+        writeln!(manifest, "{}\t{:016X}\t{}\t{}\t{}", job.name, job.chunk_id.get_chunk_id(),
+            job.chunk_id.get_chunk_index(), get_raw_chunk_type_name(&job.chunk_id.get_chunk_type()),
+            std::fs::metadata(&job.os_path)?.len())?;
+    }
+    std::fs::write(folder.join(RAW_CHUNK_MANIFEST), manifest)?;
+    Ok(jobs.len())
+}
+
+/// The store entries for every package inside of a game's containers. Used to supply metadata for
+/// mods that were made without it, since the game already has the original entry for any asset that
+/// it replaces.
+#[derive(Debug, Default)]
+pub(crate) struct GameStoreEntries(HashMap<FPackageId, StoreEntry>);
+
+impl GameStoreEntries {
+    pub(crate) fn from_paks_folder<P: AsRef<Path>>(paks: P, keys: &KeyStore, profile: &GameProfile) -> GenericResult<Self> {
+        let set = ContainerSet::open_folder(paks, keys, Some(profile))?;
+        let mut entries = HashMap::new();
+        // Containers are in mount order, so patch containers (_P) replace entries from the
+        // container that they patch
+        for (container, header) in set.containers.iter().zip(set.headers.iter()) {
+            let Some(header) = header else { continue };
+            for (id, _) in &container.toc.chunk_id_map {
+                if id.get_chunk_type() != EIoChunkType::ExportBundleData { continue; }
+                if let Some(store_entry) = header.get_store_entry(id.get_package_id()) {
+                    entries.insert(id.get_package_id(), store_entry);
+                }
+            }
+        }
+        Ok(Self(entries))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new(entries: HashMap<FPackageId, StoreEntry>) -> Self {
+        Self(entries)
+    }
+
+    pub(crate) fn get(&self, package_id: FPackageId) -> Option<&StoreEntry> {
+        self.0.get(&package_id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt::{Display, Formatter};
+use clap::ValueEnum;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use retoc::EIoChunkType;
+use utoc_lib::assets::{UASSET_EXTENSION, UBULK_EXTENSION, UMAP_EXTENSION, UPTNL_EXTENSION};
+use crate::GenericResult;
+
+const MEMORY_MAPPED_BULK_EXTENSION: &'static str = ".m.ubulk";
+
+#[derive(ValueEnum, Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+pub(crate) enum ChunkTypeFilter {
+    Package,
+    BulkData,
+    OptionalBulkData,
+    MemoryMappedBulkData
+}
+
+impl ChunkTypeFilter {
+    pub(crate) fn matches(&self, chunk_type: &EIoChunkType) -> bool {
+        match self {
+            Self::Package => *chunk_type == EIoChunkType::ExportBundleData,
+            Self::BulkData => *chunk_type == EIoChunkType::BulkData,
+            Self::OptionalBulkData => *chunk_type == EIoChunkType::OptionalBulkData,
+            Self::MemoryMappedBulkData => *chunk_type == EIoChunkType::MemoryMappedBulkData,
+        }
+    }
+}
+
+impl Display for ChunkTypeFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Self::Package => "Package",
+            Self::BulkData => "Bulk Data",
+            Self::OptionalBulkData => "Optional Bulk Data",
+            Self::MemoryMappedBulkData => "Memory Mapped Bulk Data",
+        })
+    }
+}
+
+/// Get the chunk type for an asset when only it's path is known (e.g in a pak). Memory mapped bulk
+/// data is the only chunk type with a two part extension
+pub(crate) fn chunk_type_from_path(path: &str) -> Option<EIoChunkType> {
+    if path.ends_with(MEMORY_MAPPED_BULK_EXTENSION) {
+        return Some(EIoChunkType::MemoryMappedBulkData);
+    }
+    match path.rsplit_once('.').map(|(_, ext)| ext) {
+        Some(UASSET_EXTENSION) | Some(UMAP_EXTENSION) => Some(EIoChunkType::ExportBundleData),
+        Some(UBULK_EXTENSION) => Some(EIoChunkType::BulkData),
+        Some(UPTNL_EXTENSION) => Some(EIoChunkType::OptionalBulkData),
+        _ => None
+    }
+}
+
+/// Decides which assets get unpacked. Shared between the CLI and GUI so that they both select the
+/// same files for the same set of patterns.
+/// Paths are expected in the same form as the unpacked output relative to the output folder,
+/// e.g `Game/Content/Characters/Player.uasset`
+#[derive(Debug, Default)]
+pub(crate) struct AssetFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    extensions: Vec<String>,
+    chunk_types: Vec<ChunkTypeFilter>
+}
+
+impl AssetFilter {
+    pub(crate) fn new<S: AsRef<str>>(include: &[S], exclude: &[S], extensions: &[S],
+        chunk_types: &[ChunkTypeFilter]) -> GenericResult<Self> {
+        Ok(Self {
+            include: Self::build_set(include)?,
+            exclude: Self::build_set(exclude)?,
+            extensions: extensions.iter()
+                .map(|e| e.as_ref().trim_start_matches('.').to_lowercase())
+                .filter(|e| !e.is_empty()).collect(),
+            chunk_types: chunk_types.to_vec()
+        })
+    }
+
+    /// Parse a comma separated list of patterns, as entered into the GUI's filter box
+    pub(crate) fn split_patterns(patterns: &str) -> Vec<&str> {
+        patterns.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()).collect()
+    }
+
+    fn build_set<S: AsRef<str>>(patterns: &[S]) -> GenericResult<Option<GlobSet>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let pattern = pattern.as_ref().trim_matches('/');
+            // A pattern without any wildcards is treated as a path prefix, which keeps --include
+            // working the same way as before glob patterns were supported
+            let is_literal = !pattern.contains(['*', '?', '[', '{']);
+            builder.add(GlobBuilder::new(pattern)
+                .case_insensitive(true)
+                .literal_separator(true)
+                .build()?);
+            if is_literal {
+                builder.add(GlobBuilder::new(&format!("{}/**", pattern))
+                    .case_insensitive(true)
+                    .literal_separator(true)
+                    .build()?);
+            }
+        }
+        Ok(Some(builder.build()?))
+    }
+
+    /// If chunk_type is None, then it's derived from the path's extension
+    pub(crate) fn matches(&self, path: &str, chunk_type: Option<&EIoChunkType>) -> bool {
+        if let Some(include) = &self.include && !include.is_match(path) {
+            return false;
+        }
+        if let Some(exclude) = &self.exclude && exclude.is_match(path) {
+            return false;
+        }
+        if !self.extensions.is_empty() {
+            let ext = path.rsplit_once('.').map_or(String::new(), |(_, e)| e.to_lowercase());
+            if !self.extensions.contains(&ext) {
+                return false;
+            }
+        }
+        if !self.chunk_types.is_empty() {
+            let derived = match chunk_type {
+                Some(_) => None,
+                None => chunk_type_from_path(path)
+            };
+            return match chunk_type.or(derived.as_ref()) {
+                Some(t) => self.chunk_types.iter().any(|f| f.matches(t)),
+                None => false
+            };
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use retoc::EIoChunkType;
+    use super::{chunk_type_from_path, AssetFilter, ChunkTypeFilter};
+
+    fn filter(include: &[&str], exclude: &[&str]) -> AssetFilter {
+        AssetFilter::new(include, exclude, &[], &[]).unwrap()
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let filter = filter(&["game/content/**/*.UASSET"], &[]);
+        assert!(filter.matches("Game/Content/Characters/Player.uasset", None));
+        assert!(!filter.matches("Game/Content/Characters/Player.ubulk", None));
+    }
+
+    #[test]
+    fn literal_prefix() {
+        let filter = filter(&["/Game/Content/Characters/"], &[]);
+        assert!(filter.matches("Game/Content/Characters", None));
+        assert!(filter.matches("Game/Content/Characters/Enemies/Shadow.uasset", None));
+        // Prefixes are matched by path component, not by character
+        assert!(!filter.matches("Game/Content/CharactersOld/Player.uasset", None));
+    }
+
+    #[test]
+    fn single_and_double_star() {
+        let single = filter(&["Game/Content/*.uasset"], &[]);
+        assert!(single.matches("Game/Content/Player.uasset", None));
+        assert!(!single.matches("Game/Content/Characters/Player.uasset", None));
+        let double = filter(&["Game/Content/**/*.uasset"], &[]);
+        assert!(double.matches("Game/Content/Player.uasset", None));
+        assert!(double.matches("Game/Content/Characters/Enemies/Shadow.uasset", None));
+    }
+
+    #[test]
+    fn exclude_wins() {
+        let filter = filter(&["Game/Content"], &["**/Enemies/**"]);
+        assert!(filter.matches("Game/Content/Characters/Player.uasset", None));
+        assert!(!filter.matches("Game/Content/Characters/Enemies/Shadow.uasset", None));
+    }
+
+    #[test]
+    fn extensions_and_chunk_types() {
+        let filter = AssetFilter::new::<&str>(&[], &[], &[".UBULK", "uasset"], &[ChunkTypeFilter::BulkData]).unwrap();
+        assert!(filter.matches("Game/Content/Player.ubulk", None));
+        // Explicit chunk types take priority over the one derived from the extension
+        assert!(!filter.matches("Game/Content/Player.ubulk", Some(&EIoChunkType::ExportBundleData)));
+        assert!(!filter.matches("Game/Content/Player.uasset", None));
+        assert!(!filter.matches("Game/Content/Player.uptnl", None));
+    }
+
+    #[test]
+    fn memory_mapped_bulk_data() {
+        assert_eq!(chunk_type_from_path("Game/Content/Foo.m.ubulk"), Some(EIoChunkType::MemoryMappedBulkData));
+        assert_eq!(chunk_type_from_path("Game/Content/Foo.ubulk"), Some(EIoChunkType::BulkData));
+        let filter = AssetFilter::new::<&str>(&[], &[], &[], &[ChunkTypeFilter::MemoryMappedBulkData]).unwrap();
+        assert!(filter.matches("Game/Content/Foo.m.ubulk", None));
+        assert!(filter.matches("Game/Content/Foo.m.ubulk", Some(&EIoChunkType::MemoryMappedBulkData)));
+        assert!(!filter.matches("Game/Content/Foo.ubulk", None));
+        // The extension filter still only looks at the last extension
+        let filter = AssetFilter::new::<&str>(&[], &[], &["ubulk"], &[ChunkTypeFilter::BulkData]).unwrap();
+        assert!(!filter.matches("Game/Content/Foo.m.ubulk", None));
+    }
+}
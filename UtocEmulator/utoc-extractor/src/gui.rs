@@ -3,7 +3,7 @@ use egui::Ui;
 use egui_dock::{DockArea, DockState, Style};
 use crate::actions::convert::ConvertAction;
 use crate::actions::unpack::UnpackAction;
-use crate::common::{get_config, get_egui_state, AppTabView};
+use crate::common::{get_egui_state, AppTabView};
 use crate::GenericResult;
 
 pub trait AppAction {
@@ -55,7 +55,6 @@ pub fn execute() -> GenericResult<()> {
         viewport: egui::ViewportBuilder::default().with_inner_size([720., 720.]),
         ..Default::default()
     };
-    get_config()?;
     get_egui_state()?;
     eframe::run_native(
         "Unreal Essentials UTOC Extractor", options,
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use aes::Aes256;
+use aes::cipher::KeyInit;
+use anyhow::anyhow;
+use ini::Ini;
+use retoc::FGuid;
+use retoc::ser::ReadExt;
+use utoc_lib::profiles::GameProfile;
+use crate::GenericResult;
+
+pub(crate) const KEYS_FILE: &'static str = "keys.ini";
+
+/// An encryption key GUID, stored the same way as it's serialized in the TOC header (four little
+/// endian u32s). Displayed in the same format as Unreal's EGuidFormats::Digits
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub(crate) struct KeyGuid([u8; 16]);
+
+impl KeyGuid {
+    pub(crate) fn is_default(&self) -> bool {
+        self.0 == [0; 16]
+    }
+
+    pub(crate) fn to_guid(&self) -> GenericResult<FGuid> {
+        Ok(Cursor::new(&self.0).de()?)
+    }
+}
+
+impl Display for KeyGuid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for part in self.0.chunks_exact(4) {
+            write!(f, "{:08X}", u32::from_le_bytes(part.try_into().unwrap()))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for KeyGuid {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: String = s.trim().trim_start_matches("0x").chars().filter(|c| *c != '-').collect();
+        if digits.len() != 32 {
+            return Err(anyhow!("Encryption key GUID \"{}\" must be 32 hexadecimal digits long", s));
+        }
+        let mut guid = [0; 16];
+        for i in 0..4 {
+            let part = u32::from_str_radix(&digits[i * 8..(i + 1) * 8], 16)
+                .map_err(|_| anyhow!("Encryption key GUID \"{}\" is not a hexadecimal number", s))?;
+            guid[i * 4..(i + 1) * 4].copy_from_slice(&part.to_le_bytes());
+        }
+        Ok(Self(guid))
+    }
+}
+
+/// The encryption info from a .utoc's header, read before the TOC is deserialized so that the right
+/// key can be picked for it
+#[derive(Debug)]
+pub(crate) struct TocEncryption {
+    pub(crate) guid: KeyGuid,
+    pub(crate) encrypted: bool
+}
+
+impl TocEncryption {
+    // FIoStoreTocHeader
+    const GUID_OFFSET: usize = 0x40;
+    const CONTAINER_FLAGS_OFFSET: usize = 0x50;
+    const ENCRYPTED_FLAG: u8 = 1 << 1;
+
+    pub(crate) fn read<P: AsRef<Path>>(path: P) -> GenericResult<Self> {
+        let mut header = [0; Self::CONTAINER_FLAGS_OFFSET + 1];
+        File::open(path.as_ref())?.read_exact(&mut header)?;
+        Ok(Self {
+            guid: KeyGuid(header[Self::GUID_OFFSET..Self::CONTAINER_FLAGS_OFFSET].try_into().unwrap()),
+            encrypted: header[Self::CONTAINER_FLAGS_OFFSET] & Self::ENCRYPTED_FLAG != 0
+        })
+    }
+}
+
+/// Read the encryption key GUID from a .pak's footer (FPakInfo), which is never encrypted. Returns None
+/// for paks made before encryption key GUIDs were added
+pub(crate) fn read_pak_key_guid<P: AsRef<Path>>(path: P) -> GenericResult<Option<KeyGuid>> {
+    const MAGIC: u32 = 0x5A6F12E1;
+    const ENCRYPTION_KEY_GUID_VERSION: u32 = 7;
+    // Larger than any version of FPakInfo, which is at the very end of the file
+    const FOOTER_SIZE: u64 = 0x200;
+    let mut file = File::open(path.as_ref())?;
+    file.seek(SeekFrom::Start(file.metadata()?.len().saturating_sub(FOOTER_SIZE)))?;
+    let mut footer = vec![];
+    file.read_to_end(&mut footer)?;
+    // EncryptionKeyGuid and bEncryptedIndex come before the magic, then Version after it
+    let magic = footer.windows(4).rposition(|w| w == MAGIC.to_le_bytes())
+        .filter(|m| m + 8 <= footer.len())
+        .ok_or(anyhow!("\"{}\" is not a pak", path.as_ref().to_str().unwrap()).into_boxed_dyn_error())?;
+    let version = u32::from_le_bytes(footer[magic + 4..magic + 8].try_into().unwrap());
+    if version < ENCRYPTION_KEY_GUID_VERSION || magic < 17 {
+        return Ok(None);
+    }
+    Ok(Some(KeyGuid(footer[magic - 17..magic - 1].try_into().unwrap())))
+}
+
+/// AES keys by encryption key GUID. Loaded from keys.ini, where each section is a game and each
+/// entry in that section is a GUID and it's key:
+/// [P3R]
+/// 00000000000000000000000000000000=0x...
+#[derive(Debug, Default)]
+pub(crate) struct KeyStore {
+    keys: HashMap<KeyGuid, String>,
+    source: KeySource
+}
+
+/// Where the user can enter a key that's missing, for the error when a container's key can't be found
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub(crate) enum KeySource {
+    #[default]
+    Cli,
+    Gui
+}
+
+impl KeySource {
+    fn hint(&self) -> &'static str {
+        match self {
+            Self::Cli => "pass it with --aes-key",
+            Self::Gui => "enter it into AES Key"
+        }
+    }
+}
+
+impl KeyStore {
+    /// Load the keys for one game, or every game if none is given. A key passed in through --aes-key
+    /// is used for the default (all zero) GUID
+    pub(crate) fn new(keys_file: Option<&Path>, game: Option<&str>, aes_key: Option<&str>, source: KeySource) -> GenericResult<Self> {
+        let mut keys = Self { source, ..Default::default() };
+        let keys_file = match keys_file {
+            Some(v) => Some(v.to_owned()),
+            None => get_keys_file().filter(|v| v.exists())
+        };
+        if let Some(path) = keys_file {
+            keys.add_from_file(path.as_path(), game)?;
+        }
+        if let Some(aes) = aes_key {
+            keys.keys.insert(KeyGuid::default(), aes.to_string());
+        }
+        Ok(keys)
+    }
+
+    fn add_from_file(&mut self, path: &Path, game: Option<&str>) -> GenericResult<()> {
+        let conf = Ini::load_from_file(path)?;
+        if let Some(game) = game && conf.section(Some(game)).is_none() {
+            return Err(anyhow!("Section \"{}\" is missing from {}", game, path.to_str().unwrap()).into_boxed_dyn_error());
+        }
+        for (section, props) in conf.iter() {
+            if let Some(game) = game && section != Some(game) {
+                continue;
+            }
+            for (guid, key) in props.iter() {
+                self.keys.insert(KeyGuid::from_str(guid)?, key.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Use the key for the default GUID for a game profile's key GUIDs as well, since a key passed in
+    /// through --aes-key is usually for every container in the game
+    pub(crate) fn add_profile_guids(&mut self, profile: &GameProfile) -> GenericResult<()> {
+        if let Some(key) = self.get(&KeyGuid::default()).map(|v| v.to_owned()) {
+            for guid in profile.aes_key_guids {
+                self.keys.entry(KeyGuid::from_str(guid)?).or_insert_with(|| key.clone());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, guid: &KeyGuid) -> Option<&str> {
+        self.keys.get(guid).map(|v| v.as_str())
+    }
+
+    /// Every loaded key as an AES-256 key for repak, starting with the key for guid, then the key for
+    /// the default GUID
+    pub(crate) fn iter_aes_keys(&self, guid: Option<&KeyGuid>) -> impl Iterator<Item = GenericResult<Aes256>> + '_ {
+        let mut keys: Vec<&str> = guid.and_then(|g| self.get(g)).into_iter().collect();
+        for key in self.get(&KeyGuid::default()).into_iter().chain(self.keys.values().map(|k| k.as_str())) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys.into_iter().map(parse_aes_key)
+    }
+
+    /// Find the key that a container is encrypted with. If the container has no GUID set, then the
+    /// default key is used. Returns None for unencrypted containers
+    pub(crate) fn get_for_container<P: AsRef<Path>>(&self, path: P) -> GenericResult<Option<(KeyGuid, &str)>> {
+        let encryption = TocEncryption::read(path.as_ref())?;
+        if !encryption.encrypted {
+            return Ok(None);
+        }
+        match self.get(&encryption.guid).or_else(|| match encryption.guid.is_default() {
+            true => None,
+            false => self.get(&KeyGuid::default())
+        }) {
+            Some(key) => Ok(Some((encryption.guid, key))),
+            None => Err(anyhow!("\"{}\" is encrypted with the key GUID {}, but no key was found for it. Add it to {} or {}",
+                path.as_ref().file_name().map_or("", |v| v.to_str().unwrap()), encryption.guid, KEYS_FILE, self.source.hint()).into_boxed_dyn_error())
+        }
+    }
+}
+
+pub(crate) fn parse_aes_key(key: &str) -> GenericResult<Aes256> {
+    let digits = key.trim().trim_start_matches("0x");
+    if digits.len() != 64 {
+        return Err(anyhow!("AES Key must be a 64 digit long hexadecimal number").into_boxed_dyn_error());
+    }
+    let bytes = (0..32).map(|i| u8::from_str_radix(&digits[i * 2..(i + 1) * 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(Aes256::new_from_slice(&bytes).map_err(|_| anyhow!("Invalid AES key length"))?)
+}
+
+pub(crate) fn get_keys_file() -> Option<PathBuf> {
+    let exec = std::env::current_exe().ok()?;
+    Some(exec.parent()?.join(KEYS_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::{read_pak_key_guid, KeyGuid};
+
+    #[test]
+    fn key_guid_round_trip() {
+        let text = "0123456789ABCDEF0011223344556677";
+        let guid = KeyGuid::from_str(text).unwrap();
+        assert_eq!(guid.to_string(), text);
+        // Each part is a little endian u32, the same as in the TOC header
+        assert_eq!(guid.0[..4], [0x67, 0x45, 0x23, 0x01]);
+        // Formats that Unreal and other tools write GUIDs in
+        assert_eq!(KeyGuid::from_str("0x0123456789abcdef0011223344556677").unwrap(), guid);
+        assert_eq!(KeyGuid::from_str("01234567-89ABCDEF-00112233-44556677").unwrap(), guid);
+        assert!(KeyGuid::default().is_default());
+        assert_eq!(KeyGuid::default().to_string(), "0".repeat(32));
+        assert!(KeyGuid::from_str("0123").is_err());
+        assert!(KeyGuid::from_str("0123456789ABCDEF001122334455667G").is_err());
+    }
+
+    #[test]
+    fn pak_key_guid() {
+        let guid = KeyGuid::from_str("0123456789ABCDEF0011223344556677").unwrap();
+        // Index data, then FPakInfo for version 11
+        let mut pak = vec![0xAB; 0x300];
+        pak.extend_from_slice(&guid.0);
+        pak.push(1);
+        pak.extend_from_slice(&0x5A6F12E1u32.to_le_bytes());
+        pak.extend_from_slice(&11u32.to_le_bytes());
+        pak.extend_from_slice(&[0; 8 + 8 + 20 + 5 * 32]);
+        let path = std::env::temp_dir().join(format!("utoc-extractor-key-guid-{}.pak", std::process::id()));
+        std::fs::write(&path, &pak).unwrap();
+        let result = read_pak_key_guid(&path).map_err(|e| e.to_string());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result, Ok(Some(guid)));
+    }
+}
@@ -4,12 +4,20 @@ pub(crate) mod actions {
 }
 pub(crate) mod cli;
 pub(crate) mod common;
+pub(crate) mod container;
+pub(crate) mod filter;
 pub(crate) mod gui;
+pub(crate) mod keys;
+pub(crate) mod metadata;
+pub(crate) mod pak;
 
 use std::error::Error;
 
 pub(crate) type GenericResult<T> = Result<T, Box<dyn Error>>;
 
+/// Recorded in every .utocmeta written by this tool
+pub(crate) const TOOL_VERSION: &'static str = concat!("utoc-extractor ", env!("CARGO_PKG_VERSION"));
+
 fn main() {
     let argc = std::env::args().count();
     if let Err(e) = match argc {
@@ -0,0 +1,190 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use clap::ValueEnum;
+use retoc::FPackageId;
+use retoc::ser::WriteExt;
+use retoc::version::EngineVersion;
+use walkdir::WalkDir;
+use utoc_lib::assets::{asset_path_to_package_name, convert_to_asset_path, convert_to_package_id, UASSETMETA_EXTENSION, UTOCMETA};
+use utoc_lib::metadata::{MergePolicy, UassetMetadata, UtocMetaHeader, UtocMetadata};
+use utoc_lib::text::{MetadataDocument, PackageNames};
+use crate::{GenericResult, TOOL_VERSION};
+use crate::common::{AssetMetadata, FilterByAsset};
+
+#[derive(ValueEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum TextFormat {
+    Json,
+    Toml
+}
+
+impl TextFormat {
+    pub(crate) fn get_extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml"
+        }
+    }
+
+    /// Picked from the file extension when --format isn't given. Anything other than .toml is JSON
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().map_or(false, |e| e.eq_ignore_ascii_case("toml")) {
+            true => Self::Toml,
+            false => Self::Json
+        }
+    }
+
+    pub(crate) fn write(&self, document: &MetadataDocument) -> GenericResult<String> {
+        Ok(match self {
+            Self::Json => serde_json::to_string_pretty(document)?,
+            Self::Toml => toml::to_string_pretty(document)?
+        })
+    }
+
+    pub(crate) fn read(&self, text: &str) -> GenericResult<MetadataDocument> {
+        Ok(match self {
+            Self::Json => serde_json::from_str(text)?,
+            Self::Toml => toml::from_str(text)?
+        })
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum MergeOption {
+    /// Keep the entry from the first source that has it
+    First,
+    /// Use the entry from the last source that has it
+    Last,
+    /// Stop without writing anything
+    Error
+}
+
+impl MergeOption {
+    pub(crate) fn to_policy(&self) -> MergePolicy {
+        match self {
+            Self::First => MergePolicy::KeepExisting,
+            Self::Last => MergePolicy::Replace,
+            Self::Error => MergePolicy::Fail
+        }
+    }
+}
+
+/// Load metadata from either a mod's UnrealEssentials folder or a .utocmeta file
+pub(crate) fn load_metadata_source<P: AsRef<Path>>(path: P, version: EngineVersion, names: &mut PackageNames) -> GenericResult<UtocMetadata> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        let mod_meta = ModMetadata::new(path)?;
+        mod_meta.add_package_names(names);
+        return mod_meta.load(version);
+    }
+    let mut metadata = UtocMetadata::default();
+    metadata.add_from_utocmeta(std::fs::read(path)?.as_slice(), version)?;
+    Ok(metadata)
+}
+
+/// The assets and asset metadata inside of a mod's UnrealEssentials folder
+pub(crate) struct ModMetadata {
+    pub(crate) path: PathBuf,
+    pub(crate) assets: Vec<PathBuf>,
+    pub(crate) format: AssetMetadata
+}
+
+impl ModMetadata {
+    /// Find the assets in a mod and which type of asset metadata it has. A mod can't have both types
+    pub(crate) fn new<P: AsRef<Path>>(input: P) -> GenericResult<Self> {
+        let path = input.as_ref().to_owned();
+        let assets: Vec<_> = WalkDir::new(path.as_path()).into_iter()
+            .filter_map(|d| FilterByAsset::filter_by_asset_path(path.as_path(), d)).collect();
+        let has_toc_meta = std::fs::read_dir(path.as_path())?
+            .find(FilterByAsset::check_utocmeta).is_some();
+        let has_asset_meta = assets.iter()
+            .any(|v| path.join(v).with_extension(UASSETMETA_EXTENSION).exists());
+        let format = match (has_toc_meta, has_asset_meta) {
+            (true, true) => return Err(anyhow!("Expected the mod to only have one type of asset metadata.").into_boxed_dyn_error()),
+            (true, false) => AssetMetadata::Table,
+            (false, true) => AssetMetadata::PerAsset,
+            (false, false) => AssetMetadata::None
+        };
+        Ok(Self { path, assets, format })
+    }
+
+    /// Converting replaces the metadata for every asset, so each of them needs a .uassetmeta
+    pub(crate) fn check_complete(&self) -> GenericResult<()> {
+        if self.format == AssetMetadata::PerAsset && self.assets.iter()
+            .any(|v| !self.path.join(v).with_extension(UASSETMETA_EXTENSION).exists()) {
+            return Err(anyhow!("Expected every asset to have an associated .uassetmeta.").into_boxed_dyn_error());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_package_id(&self, asset: &Path) -> FPackageId {
+        convert_to_package_id(self.path.join(asset), self.path.as_path(), None)
+    }
+
+    pub(crate) fn load(&self, version: EngineVersion) -> GenericResult<UtocMetadata> {
+        let mut metadata = UtocMetadata::default();
+        match self.format {
+            AssetMetadata::Table => {
+                metadata.add_from_utocmeta(std::fs::read(self.path.join(UTOCMETA))?.as_slice(), version)?;
+            },
+            AssetMetadata::PerAsset => {
+                for asset in &self.assets {
+                    let meta_path = self.path.join(asset).with_extension(UASSETMETA_EXTENSION);
+                    if meta_path.exists() {
+                        metadata.add_from_uassetmeta(self.get_package_id(asset), &[], meta_path.as_path(), version)?;
+                    }
+                }
+            },
+            AssetMetadata::None => return Err(anyhow!("No asset metadata exists in this mod.").into_boxed_dyn_error())
+        }
+        Ok(metadata)
+    }
+
+    pub(crate) fn add_package_names(&self, names: &mut PackageNames) {
+        for asset in &self.assets {
+            let asset_path = convert_to_asset_path(self.path.join(asset), self.path.as_path(), None);
+            names.add(asset_path_to_package_name(&asset_path));
+        }
+    }
+
+    /// Write metadata in the given format, replacing whatever metadata the mod had before
+    pub(crate) fn save(&mut self, metadata: UtocMetadata, format: AssetMetadata, version: EngineVersion) -> GenericResult<()> {
+        let toc_path = self.path.join(UTOCMETA);
+        match format {
+            AssetMetadata::Table => {
+                let mut writer = BufWriter::new(File::create(toc_path)?);
+                metadata.serialize(&mut writer, &UtocMetaHeader::new(version.container_header_version(), Some(version), TOOL_VERSION))?;
+            },
+            AssetMetadata::PerAsset => {
+                let parts = metadata.into_parts();
+                if !parts.alt_import_assets.is_empty() || !parts.manual_import_assets.is_empty() {
+                    return Err(anyhow!("Manual and validated import lists can only be stored in a .utocmeta").into_boxed_dyn_error());
+                }
+                for (package_id, store_entry) in parts.store_entries {
+                    match self.assets.iter().find(|a| self.get_package_id(a) == package_id) {
+                        Some(asset) => {
+                            let mut meta_file = File::create(self.path.join(asset).with_extension(UASSETMETA_EXTENSION))?;
+                            meta_file.ser(&UassetMetadata::new(version.container_header_version(), package_id, store_entry))?;
+                        },
+                        None => println!("{}: Skipping store entry for package {:?} since there's no asset for it in this mod", console::style("WARNING").yellow(), package_id)
+                    }
+                }
+                if toc_path.exists() {
+                    std::fs::remove_file(toc_path)?;
+                }
+            },
+            AssetMetadata::None => unreachable!()
+        }
+        if format == AssetMetadata::Table && self.format == AssetMetadata::PerAsset {
+            for asset in &self.assets {
+                let meta_path = self.path.join(asset).with_extension(UASSETMETA_EXTENSION);
+                if meta_path.exists() {
+                    std::fs::remove_file(meta_path)?;
+                }
+            }
+        }
+        self.format = format;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use indicatif::ProgressBar;
+use rayon::{ThreadPoolBuilder, prelude::*};
+use repak::{PakBuilder, PakReader};
+use crate::GenericResult;
+use crate::keys::{read_pak_key_guid, KeyStore};
+
+pub(crate) const PAK_EXTENSION: &'static str = "pak";
+
+pub(crate) fn is_pak<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().map_or(false, |e| e.eq_ignore_ascii_case(PAK_EXTENSION))
+}
+
+/// A legacy .pak archive. Assets inside of these are in the cooked .uasset/.uexp format rather than
+/// Zen assets, so there's no store entries to generate asset metadata from
+pub(crate) struct LegacyPak {
+    pak: PakReader,
+    path: PathBuf
+}
+
+impl LegacyPak {
+    /// Each key is tried in turn, starting with the key for the pak's encryption key GUID. Paks are only
+    /// opened without a key when none of them work, since a pak with an unencrypted index can still
+    /// have encrypted files, which would fail to extract
+    pub(crate) fn open<P: AsRef<Path>>(path: P, keys: &KeyStore) -> GenericResult<Self> {
+        let path = path.as_ref().to_owned();
+        let guid = read_pak_key_guid(path.as_path())?;
+        for key in keys.iter_aes_keys(guid.as_ref()) {
+            let mut reader = BufReader::new(File::open(path.as_path())?);
+            if let Ok(pak) = PakBuilder::new().key(key?).reader(&mut reader) {
+                return Ok(Self { pak, path });
+            }
+        }
+        let mut reader = BufReader::new(File::open(path.as_path())?);
+        if let Ok(pak) = PakBuilder::new().reader(&mut reader) {
+            return Ok(Self { pak, path });
+        }
+        Err(anyhow!("Could not open \"{}\" with any of the loaded AES keys", path.to_str().unwrap()).into_boxed_dyn_error())
+    }
+
+    pub(crate) fn files(&self) -> Vec<String> {
+        self.pak.files()
+    }
+
+    /// Same layout as get_root_path for IO Store containers. Paks mounted at the root already start
+    /// with the project name (e.g P3R/Content/...)
+    pub(crate) fn get_root_path<P: AsRef<Path>>(&self, output: P) -> PathBuf {
+        let mount_point = self.pak.mount_point();
+        match mount_point.len() > utoc_lib::assets::MOUNT_POINT.len() {
+            true => output.as_ref().join(&mount_point[utoc_lib::assets::MOUNT_POINT.len()..]),
+            false => output.as_ref().to_owned()
+        }
+    }
+
+    /// Extract (file in pak, path on disk) pairs. Each thread gets it's own handle to the pak
+    pub(crate) fn extract(&self, files: &[(String, PathBuf)], threads: usize, bar: &ProgressBar) -> GenericResult<()> {
+        let pool = ThreadPoolBuilder::new().num_threads(threads.max(1)).build()?;
+        pool.install(|| files.par_iter().map_init(
+            || File::open(self.path.as_path()).map(BufReader::new),
+            |reader, (file, os_path)| -> anyhow::Result<()> {
+                let reader = reader.as_mut().map_err(|e| anyhow!("{}", e))?;
+                let data = self.pak.get(file, reader)?;
+                std::fs::create_dir_all(os_path.parent().unwrap())?;
+                std::fs::write(os_path, &data)?;
+                bar.set_message(file.clone());
+                bar.inc(1);
+                Ok(())
+            }).collect::<anyhow::Result<()>>())?;
+        Ok(())
+    }
+}
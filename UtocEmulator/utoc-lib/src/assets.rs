@@ -1,5 +1,9 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use retoc::{lower_utf16_cityhash, FPackageId};
+use crate::GenericResult;
 
 pub const MOUNT_POINT:  &'static str = "../../../";
 
@@ -62,6 +66,12 @@ where P0: AsRef<Path>, P1: AsRef<Path> {
             path
         }
     };
+    get_asset_path(&path, vpath)
+}
+
+/// The input path is expected to be relative to the mount point, using / as the separator:
+/// e.g P3R/Content/...
+pub fn get_asset_path(path: &str, vpath: Option<&PathBuf>) -> String {
     let parts: Vec<&str> = path.splitn(3, "/").collect();
     // check that path is that long
     let domain = match parts[0] {
@@ -84,18 +94,60 @@ where P0: AsRef<Path>, P1: AsRef<Path> {
 }
 
 pub fn asset_path_to_package_id(asset_path: &str) -> FPackageId {
-    let asset_path = asset_path[MOUNT_POINT.len() - 1..].rsplit_once('.').unwrap().0;
-    FPackageId(lower_utf16_cityhash(asset_path))
+    FPackageId(lower_utf16_cityhash(asset_path_to_package_name(asset_path)))
 }
 
-#[derive(Debug)]
+/// e.g ../../../Game/Characters/Player.uasset becomes /Game/Characters/Player
+pub fn asset_path_to_package_name(asset_path: &str) -> &str {
+    asset_path[MOUNT_POINT.len() - 1..].rsplit_once('.').unwrap().0
+}
+
+#[derive(Debug, Clone)]
 pub struct AssetEntry {
     pub os_path: PathBuf,
     pub size: u64,
+    /// The asset's contents if it was registered from memory rather than from a file. os_path is
+    /// then only used for the asset's extension and in logs
+    pub data: Option<Arc<[u8]>>,
 }
 
 impl AssetEntry {
     pub fn new(os_path: PathBuf, size: u64) -> Self {
-        Self { os_path, size }
+        Self { os_path, size, data: None }
+    }
+
+    pub fn from_memory(path: PathBuf, data: Arc<[u8]>) -> Self {
+        Self { os_path: path, size: data.len() as u64, data: Some(data) }
+    }
+
+    pub fn open(&self) -> GenericResult<AssetReader> {
+        Ok(match &self.data {
+            Some(data) => AssetReader::Memory(Cursor::new(data.clone())),
+            None => AssetReader::File(BufReader::with_capacity(0x2000, File::open(self.os_path.as_path())?))
+        })
+    }
+}
+
+/// Reads an asset from either a file or memory
+pub enum AssetReader {
+    File(BufReader<File>),
+    Memory(Cursor<Arc<[u8]>>)
+}
+
+impl Read for AssetReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::File(f) => f.read(buf),
+            Self::Memory(m) => m.read(buf)
+        }
+    }
+}
+
+impl Seek for AssetReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            Self::File(f) => f.seek(pos),
+            Self::Memory(m) => m.seek(pos)
+        }
     }
 }
\ No newline at end of file
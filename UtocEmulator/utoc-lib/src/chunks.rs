@@ -0,0 +1,66 @@
+use retoc::{EIoChunkType, FIoChunkId};
+
+/// Chunks that don't have a path in the directory index (shader code, external files, the container
+/// header...) are stored in this folder, named by their chunk id and chunk type:
+/// e.g _chunks/0123456789ABCDEF_0000.ShaderCodeLibrary
+pub const RAW_CHUNK_FOLDER: &'static str = "_chunks";
+pub const RAW_CHUNK_MANIFEST: &'static str = "manifest.txt";
+
+/// The extension used for each chunk type. These are saved into mods, so they must never change
+/// (even if retoc renames a chunk type)
+pub static RAW_CHUNK_TYPES: [(EIoChunkType, &'static str); 13] = [
+    (EIoChunkType::ExportBundleData, "ExportBundleData"),
+    (EIoChunkType::BulkData, "BulkData"),
+    (EIoChunkType::OptionalBulkData, "OptionalBulkData"),
+    (EIoChunkType::MemoryMappedBulkData, "MemoryMappedBulkData"),
+    (EIoChunkType::ScriptObjects, "ScriptObjects"),
+    (EIoChunkType::ContainerHeader, "ContainerHeader"),
+    (EIoChunkType::ExternalFile, "ExternalFile"),
+    (EIoChunkType::ShaderCodeLibrary, "ShaderCodeLibrary"),
+    (EIoChunkType::ShaderCode, "ShaderCode"),
+    (EIoChunkType::PackageStoreEntry, "PackageStoreEntry"),
+    (EIoChunkType::DerivedData, "DerivedData"),
+    (EIoChunkType::EditorDerivedData, "EditorDerivedData"),
+    (EIoChunkType::PackageResource, "PackageResource"),
+];
+
+/// Chunk types that aren't in RAW_CHUNK_TYPES are named Unknown, which isn't read back
+pub fn get_raw_chunk_type_name(chunk_type: &EIoChunkType) -> &'static str {
+    RAW_CHUNK_TYPES.iter().find(|(t, _)| t == chunk_type).map_or("Unknown", |(_, n)| *n)
+}
+
+pub fn get_raw_chunk_file_name(chunk_id: &FIoChunkId) -> String {
+    format!("{:016X}_{:04X}.{}", chunk_id.get_chunk_id(), chunk_id.get_chunk_index(),
+        get_raw_chunk_type_name(&chunk_id.get_chunk_type()))
+}
+
+pub fn from_raw_chunk_file_name(name: &str) -> Option<FIoChunkId> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    let (id, index) = stem.split_once('_')?;
+    let (chunk_type, _) = RAW_CHUNK_TYPES.iter()
+        .find(|(_, n)| n.eq_ignore_ascii_case(ext))?;
+    Some(FIoChunkId::create(
+        u64::from_str_radix(id, 16).ok()?,
+        u16::from_str_radix(index, 16).ok()?,
+        chunk_type.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use retoc::FIoChunkId;
+    use super::{from_raw_chunk_file_name, get_raw_chunk_file_name, RAW_CHUNK_TYPES};
+
+    #[test]
+    fn raw_chunk_file_names() {
+        for (chunk_type, name) in &RAW_CHUNK_TYPES {
+            let chunk_id = FIoChunkId::create(0x0123456789ABCDEF, 0x12, chunk_type.clone());
+            let file_name = get_raw_chunk_file_name(&chunk_id);
+            assert_eq!(file_name, format!("0123456789ABCDEF_0012.{}", name));
+            assert_eq!(from_raw_chunk_file_name(&file_name), Some(chunk_id));
+        }
+        // Extensions are matched case-insensitively, since the file may have been renamed on Windows
+        assert!(from_raw_chunk_file_name("0123456789ABCDEF_0000.shadercodelibrary").is_some());
+        assert!(from_raw_chunk_file_name("0123456789ABCDEF_0000.Unknown").is_none());
+        assert!(from_raw_chunk_file_name("0123456789ABCDEF.BulkData").is_none());
+    }
+}
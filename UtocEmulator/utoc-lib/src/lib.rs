@@ -1,6 +1,9 @@
 pub mod assets;
+pub mod chunks;
 pub mod metadata;
+pub mod profiles;
 pub mod store;
+pub mod text;
 
 use std::error::Error;
 
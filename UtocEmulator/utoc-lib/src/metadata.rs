@@ -13,12 +13,17 @@ use crate::GenericResult;
 // version: u32 @ 0x0
 // alt_auto_import_count: u32 @ 0x4
 // manual_import_count: u32 @ 0x8
-// compressed_package_count: u32 @ 0xc
+// compressed_package_count: u32 @ 0xc (version 1 only)
+// container_header_version: u32 @ 0xc (version 3+)
+// engine_version: u32 @ 0x10 (version 3+, 0 if unknown)
+// checksum: u64 @ 0x14 (version 3+, FNV-1a of the file without the checksum)
+// tool_version: u32 length, [u8; length] @ 0x1c (version 3+)
 // alt_import_assets: [u64; length] @ alt_auto_imports_offset
 // manual_import_assets @ manual_imports_offset
 //      asset_hash: u64,
 //      count: u64,
 //      imports: [u64; count]
+// fast_resolve_assets: StoreEntries (version 2+)
 #[derive(Debug, PartialEq)]
 pub struct UtocMetadata {
     alt_import_assets: HashSet<FPackageId>,
@@ -26,6 +31,33 @@ pub struct UtocMetadata {
     fast_resolve_assets: StoreEntries
 }
 
+#[derive(Debug, Default)]
+pub struct UtocMetadataParts {
+    pub alt_import_assets: Vec<FPackageId>,
+    pub manual_import_assets: Vec<(FPackageId, Vec<FPackageId>)>,
+    pub store_entries: Vec<(FPackageId, StoreEntry)>
+}
+
+/// What to do when two sources of metadata have a different entry for the same package
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MergePolicy {
+    KeepExisting,
+    Replace,
+    Fail
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MergeSection {
+    StoreEntry,
+    ManualImports
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MergeConflict {
+    pub package_id: FPackageId,
+    pub section: MergeSection
+}
+
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub enum UtocMetaImportType {
     GraphPackageUnvalidated,
@@ -38,7 +70,87 @@ pub enum UtocMetaImportType {
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub enum UtocMetaVersion {
     Initial = 1,
-    FastResolver
+    FastResolver,
+    Versioned
+}
+
+/// Engine versions are stored as their position in this list (plus one), so new versions must only
+/// be added to the end
+static UTOCMETA_ENGINE_VERSIONS: [EngineVersion; 11] = [
+    EngineVersion::UE4_25,
+    EngineVersion::UE4_26,
+    EngineVersion::UE4_27,
+    EngineVersion::UE5_0,
+    EngineVersion::UE5_1,
+    EngineVersion::UE5_2,
+    EngineVersion::UE5_3,
+    EngineVersion::UE5_4,
+    EngineVersion::UE5_5,
+    EngineVersion::UE5_6,
+    EngineVersion::UE5_7,
+];
+
+/// Describes what a .utocmeta's store entries were generated for. Files from before version 3 don't
+/// record this, so only the version is known for them
+#[derive(Debug, Clone, PartialEq)]
+pub struct UtocMetaHeader {
+    pub version: UtocMetaVersion,
+    pub container_header_version: Option<EIoContainerHeaderVersion>,
+    pub engine_version: Option<EngineVersion>,
+    pub tool_version: Option<String>
+}
+
+impl UtocMetaHeader {
+    const CHECKSUM_OFFSET: usize = 0x14;
+    const CHECKSUM_END: usize = Self::CHECKSUM_OFFSET + size_of::<u64>();
+
+    pub fn new(container_header_version: EIoContainerHeaderVersion, engine_version: Option<EngineVersion>, tool_version: &str) -> Self {
+        Self {
+            version: UtocMetaVersion::Versioned,
+            container_header_version: Some(container_header_version),
+            engine_version,
+            tool_version: Some(tool_version.to_owned())
+        }
+    }
+
+    fn engine_to_raw(engine: Option<EngineVersion>) -> u32 {
+        engine.and_then(|e| UTOCMETA_ENGINE_VERSIONS.iter().position(|v| *v == e))
+            .map_or(0, |i| i as u32 + 1)
+    }
+
+    fn engine_from_raw(raw: u32) -> GenericResult<Option<EngineVersion>> {
+        match raw {
+            0 => Ok(None),
+            _ => UTOCMETA_ENGINE_VERSIONS.get(raw as usize - 1).map(|v| Some(*v))
+                .ok_or(anyhow!("Unknown engine version {} in utocmeta", raw).into_boxed_dyn_error())
+        }
+    }
+
+    pub fn can_load_for(&self, target: EIoContainerHeaderVersion) -> bool {
+        self.container_header_version.map_or(true, |v| can_load_store_entries(v, target))
+    }
+}
+
+fn container_header_version_from_raw(raw: u32) -> GenericResult<EIoContainerHeaderVersion> {
+    UTOCMETA_ENGINE_VERSIONS.iter().map(|v| v.container_header_version())
+        .find(|v| *v as u32 == raw)
+        .ok_or(anyhow!("Unknown container header version {}", raw).into_boxed_dyn_error())
+}
+
+/// Store entries can be read for a different engine as long as it's container header version is
+/// the same, or both versions are at least NoExportInfo (UE 5.3+) where the entry only gains new
+/// fields. Anything else would be mis-parsed
+pub fn can_load_store_entries(from: EIoContainerHeaderVersion, to: EIoContainerHeaderVersion) -> bool {
+    from == to || (from >= EIoContainerHeaderVersion::NoExportInfo && to >= EIoContainerHeaderVersion::NoExportInfo)
+}
+
+fn fnv1a_64(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn utocmeta_checksum(data: &[u8]) -> u64 {
+    let hash = fnv1a_64(0xcbf29ce484222325, &data[..UtocMetaHeader::CHECKSUM_OFFSET]);
+    fnv1a_64(hash, &data[UtocMetaHeader::CHECKSUM_END..])
 }
 
 impl Writeable for UtocMetaVersion {
@@ -56,8 +168,93 @@ impl Readable for UtocMetaVersion {
         match raw {
             1 => Ok(UtocMetaVersion::Initial),
             2 => Ok(UtocMetaVersion::FastResolver),
-            _ => Err(anyhow!("Unknown utocmeta version {}, expected version 1 or 2", raw))
+            3 => Ok(UtocMetaVersion::Versioned),
+            _ => Err(anyhow!("Unknown utocmeta version {}, expected version 1, 2 or 3", raw))
+        }
+    }
+}
+
+// .uassetmeta structure:
+// magic: [u8; 4] = "UAMT" @ 0x0
+// version: u32 @ 0x4
+// container_header_version: u32 @ 0x8
+// package_id: u64 @ 0xc
+// store_entry: StoreEntry @ 0x14
+// Files from before the header was added only contain the store entry
+pub const UASSETMETA_MAGIC: [u8; 4] = *b"UAMT";
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub enum UassetMetaVersion {
+    Initial = 1
+}
+
+impl Writeable for UassetMetaVersion {
+    fn ser<S: Write>(&self, stream: &mut S) -> anyhow::Result<()> {
+        stream.ser(&(*self as u32))
+    }
+}
+
+impl Readable for UassetMetaVersion {
+    fn de<S: Read>(stream: &mut S) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let raw: u32 = stream.de()?;
+        match raw {
+            1 => Ok(UassetMetaVersion::Initial),
+            _ => Err(anyhow!("Unknown uassetmeta version {}, expected version 1", raw))
+        }
+    }
+}
+
+/// The store entry for a single asset, tagged with the package it belongs to and the container header
+/// version it was generated for
+#[derive(Debug, Clone)]
+pub struct UassetMetadata {
+    pub container_header_version: EIoContainerHeaderVersion,
+    pub package_id: FPackageId,
+    pub store_entry: StoreEntry
+}
+
+impl UassetMetadata {
+    pub fn new(container_header_version: EIoContainerHeaderVersion, package_id: FPackageId, store_entry: StoreEntry) -> Self {
+        Self { container_header_version, package_id, store_entry }
+    }
+
+    /// Read the store entry for an asset. package_ids are every id that the asset is known by (e.g with
+    /// and without a virtual mount path). Headerless files are read as they are, since there's nothing
+    /// in them to check against
+    pub fn read(data: &[u8], package_ids: &[FPackageId], engine: EngineVersion) -> GenericResult<StoreEntry> {
+        let mut reader = Cursor::new(data);
+        if !data.starts_with(&UASSETMETA_MAGIC) {
+            return Ok(reader.de().map_err(|e| anyhow!("Could not read headerless uassetmeta: {}", e))?);
         }
+        reader.set_position(UASSETMETA_MAGIC.len() as u64);
+        let _: UassetMetaVersion = reader.de()?;
+        let header_version = container_header_version_from_raw(reader.de()?)?;
+        let id: FPackageId = reader.de()?;
+        if !package_ids.contains(&id) {
+            return Err(anyhow!("uassetmeta belongs to package {:?}, but the asset next to it is package {:?}. Was the asset moved or renamed?", id, package_ids[0]).into_boxed_dyn_error());
+        }
+        if !can_load_store_entries(header_version, engine.container_header_version()) {
+            return Err(anyhow!("uassetmeta was made for container header version {:?} and can't be used for {:?}", header_version, engine).into_boxed_dyn_error());
+        }
+        let store_entry: StoreEntry = reader.de().map_err(|_| anyhow!("uassetmeta is truncated"))?;
+        if reader.position() != data.len() as u64 {
+            return Err(anyhow!("uassetmeta has {} unexpected bytes after the store entry", data.len() as u64 - reader.position()).into_boxed_dyn_error());
+        }
+        Ok(store_entry)
+    }
+}
+
+impl Writeable for UassetMetadata {
+    fn ser<S: Write>(&self, stream: &mut S) -> anyhow::Result<()> {
+        stream.write_all(&UASSETMETA_MAGIC)?;
+        stream.ser(&UassetMetaVersion::Initial)?;
+        stream.ser(&(self.container_header_version as u32))?;
+        stream.ser(&self.package_id)?;
+        stream.ser(&self.store_entry)
     }
 }
 
@@ -72,7 +269,9 @@ impl Default for UtocMetadata {
 }
 
 impl UtocMetadata {
-    pub fn add_from_utocmeta(&mut self, data: &[u8], engine: EngineVersion) -> GenericResult<()> {
+    /// Returns the file's header. Version 3 files are checked against their checksum, and refused if
+    /// their store entries can't be read for this engine version
+    pub fn add_from_utocmeta(&mut self, data: &[u8], engine: EngineVersion) -> GenericResult<UtocMetaHeader> {
         let mut reader = Cursor::new(data);
         // read header
         let version: UtocMetaVersion = reader.de()?;
@@ -80,15 +279,34 @@ impl UtocMetadata {
         let manual_v1_count: u32 = reader.de()?;
         let compressed_package_count: u32 = match version {
             UtocMetaVersion::Initial => reader.de()?,
-            UtocMetaVersion::FastResolver => 0
+            _ => 0
         };
+        let mut header = UtocMetaHeader {
+            version, container_header_version: None, engine_version: None, tool_version: None };
+        if version >= UtocMetaVersion::Versioned {
+            header.container_header_version = Some(container_header_version_from_raw(reader.de()?)?);
+            header.engine_version = UtocMetaHeader::engine_from_raw(reader.de()?)?;
+            let checksum: u64 = reader.de()?;
+            if checksum != utocmeta_checksum(data) {
+                return Err(anyhow!("utocmeta checksum does not match, the file may be corrupted").into_boxed_dyn_error());
+            }
+            let tool_length: u32 = reader.de()?;
+            let mut tool = vec![0; tool_length as usize];
+            reader.read_exact(&mut tool)?;
+            header.tool_version = Some(String::from_utf8(tool)?);
+            if !header.can_load_for(engine.container_header_version()) {
+                return Err(anyhow!("utocmeta was made for {} (container header version {:?}) and can't be used for {:?}",
+                    header.engine_version.map_or("an unknown engine version".to_string(), |v| format!("{:?}", v)),
+                    header.container_header_version.unwrap(), engine).into_boxed_dyn_error());
+            }
+        }
         // read alt auto imports
         for _ in 0..alt_auto_import_count {
             self.alt_import_assets.insert(reader.de()?);
         }
         // read manual imports
         for _ in 0..manual_v1_count {
-            let (manual_asset, import_count) = (reader.de()?, reader.de()?);
+            let (manual_asset, import_count): (FPackageId, u64) = (reader.de()?, reader.de()?);
             let manual_imports: Vec<FPackageId> = (0..import_count).filter_map(|_| reader.de().ok()).collect();
             self.manual_import_assets.insert(manual_asset, manual_imports);
         }
@@ -99,18 +317,25 @@ impl UtocMetadata {
                     let _: Vec<FPackageId> = reader.de()?;
                 }
             },
-            UtocMetaVersion::FastResolver => {
+            _ => {
+                let store_version = header.container_header_version
+                    .unwrap_or(engine.container_header_version());
                 for (id, entry) in StoreEntries::deserialize(
-                    &mut reader, engine.container_header_version())?.into_iter() {
+                    &mut reader, store_version)?.into_iter() {
                     self.fast_resolve_assets.insert(id, entry);
                 }
             }
         }
-        Ok(())
+        Ok(header)
     }
 
-    pub fn add_from_uassetmeta(&mut self, key: FPackageId, path: &Path) -> GenericResult<()> {
-        self.fast_resolve_assets.insert(key, Cursor::new(std::fs::read(path)?).de()?);
+    /// aliases are other ids that the uassetmeta may have been written for, such as the id of the asset
+    /// without it's virtual mount path
+    pub fn add_from_uassetmeta(&mut self, key: FPackageId, aliases: &[FPackageId], path: &Path, engine: EngineVersion) -> GenericResult<()> {
+        let ids: Vec<_> = std::iter::once(key).chain(aliases.iter().copied()).collect();
+        let store_entry = UassetMetadata::read(std::fs::read(path)?.as_slice(), ids.as_slice(), engine)
+            .map_err(|e| anyhow!("{}: {}", path.to_str().unwrap(), e))?;
+        self.fast_resolve_assets.insert(key, store_entry);
         Ok(())
     }
 
@@ -123,6 +348,70 @@ impl UtocMetadata {
         self.alt_import_assets.insert(key);
     }
 
+    pub fn add_manual_v1_import(&mut self, key: FPackageId, imports: Vec<FPackageId>) {
+        self.manual_import_assets.insert(key, imports);
+    }
+
+    /// Add every entry from other into this metadata. Entries that are the same in both aren't
+    /// conflicts. With MergePolicy::Fail, nothing is merged if there are any conflicts.
+    /// Returns the conflicts that were resolved using the policy
+    pub fn merge(&mut self, other: UtocMetadata, policy: MergePolicy) -> GenericResult<Vec<MergeConflict>> {
+        let parts = other.into_parts();
+        let mut conflicts: Vec<MergeConflict> = parts.store_entries.iter()
+            .filter(|(id, entry)| self.fast_resolve_assets.get(*id).is_some_and(|v| v != *entry))
+            .map(|(id, _)| MergeConflict { package_id: *id, section: MergeSection::StoreEntry })
+            .collect();
+        conflicts.extend(parts.manual_import_assets.iter()
+            .filter(|(id, imports)| self.manual_import_assets.get(id).is_some_and(|v| v != imports))
+            .map(|(id, _)| MergeConflict { package_id: *id, section: MergeSection::ManualImports }));
+        if policy == MergePolicy::Fail && !conflicts.is_empty() {
+            let ids: Vec<_> = conflicts.iter().map(|c| format!("0x{:016X} ({:?})", c.package_id.0, c.section)).collect();
+            return Err(anyhow!("{} packages have conflicting metadata: {}", conflicts.len(), ids.join(", ")).into_boxed_dyn_error());
+        }
+        self.alt_import_assets.extend(parts.alt_import_assets);
+        for (id, imports) in parts.manual_import_assets {
+            if policy == MergePolicy::Replace || !self.manual_import_assets.contains_key(&id) {
+                self.manual_import_assets.insert(id, imports);
+            }
+        }
+        for (id, entry) in parts.store_entries {
+            if policy == MergePolicy::Replace || !self.fast_resolve_assets.contains(id) {
+                self.fast_resolve_assets.insert(id, entry);
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Move entries to different package ids, e.g when a mod is mounted at a virtual path after it's
+    /// metadata was generated. Ids that aren't in the map are kept as they are
+    pub fn rekey(self, ids: &HashMap<FPackageId, FPackageId>) -> Self {
+        let get = |id: FPackageId| ids.get(&id).copied().unwrap_or(id);
+        let parts = self.into_parts();
+        let mut metadata = Self::default();
+        metadata.alt_import_assets.extend(parts.alt_import_assets.into_iter().map(get));
+        for (id, imports) in parts.manual_import_assets {
+            metadata.manual_import_assets.insert(get(id), imports);
+        }
+        for (id, entry) in parts.store_entries {
+            metadata.fast_resolve_assets.insert(get(id), entry);
+        }
+        metadata
+    }
+
+    /// Split the metadata into each of it's sections, sorted by package id so that the output is the
+    /// same every time for the same metadata
+    pub fn into_parts(self) -> UtocMetadataParts {
+        let mut parts = UtocMetadataParts {
+            alt_import_assets: self.alt_import_assets.into_iter().collect(),
+            manual_import_assets: self.manual_import_assets.into_iter().collect(),
+            store_entries: self.fast_resolve_assets.into_iter().collect()
+        };
+        parts.alt_import_assets.sort_by_key(|v| v.0);
+        parts.manual_import_assets.sort_by_key(|v| v.0.0);
+        parts.store_entries.sort_by_key(|v| v.0.0);
+        parts
+    }
+
 
     pub fn get_import_type(&self, asset: FPackageId) -> UtocMetaImportType {
         if self.fast_resolve_assets.contains(asset) {
@@ -144,15 +433,129 @@ impl UtocMetadata {
         self.fast_resolve_assets.get(asset)
     }
 
-    pub fn serialize<S: Write>(&self, stream: &mut S, version: EIoContainerHeaderVersion) -> GenericResult<()> {
-        stream.ser(&UtocMetaVersion::FastResolver)?;
-        stream.ser(&(self.alt_import_assets.len() as u32))?;
-        stream.ser(&(self.manual_import_assets.len() as u32))?;
+    pub fn get_manual_v1_assets(&self) -> impl Iterator<Item = &FPackageId> {
+        self.manual_import_assets.keys()
+    }
+
+    /// Used once a manual import list has been turned into a store entry, since the store entry takes
+    /// priority over it anyway
+    pub fn remove_manual_v1_import(&mut self, asset: FPackageId) -> Option<Vec<FPackageId>> {
+        self.manual_import_assets.remove(&asset)
+    }
+
+    pub fn serialize<S: Write>(&self, stream: &mut S, header: &UtocMetaHeader) -> GenericResult<()> {
+        let version = header.container_header_version
+            .ok_or(anyhow!("A container header version is required to write a utocmeta"))?;
+        let tool = header.tool_version.as_ref().map_or("", |v| v.as_str());
+        let mut data = Cursor::new(vec![]);
+        data.ser(&UtocMetaVersion::Versioned)?;
+        data.ser(&(self.alt_import_assets.len() as u32))?;
+        data.ser(&(self.manual_import_assets.len() as u32))?;
+        data.ser(&(version as u32))?;
+        data.ser(&UtocMetaHeader::engine_to_raw(header.engine_version))?;
+        data.ser(&0u64)?; // checksum, filled in at the end
+        data.ser(&(tool.len() as u32))?;
+        data.write_all(tool.as_bytes())?;
         for import in &self.alt_import_assets {
-            stream.ser(import)?;
+            data.ser(import)?;
         }
-        // stream.ser(&(0u32))?;
-        self.fast_resolve_assets.serialize(stream, version)?;
+        for (asset, imports) in &self.manual_import_assets {
+            data.ser(asset)?;
+            data.ser(&(imports.len() as u64))?;
+            for import in imports {
+                data.ser(import)?;
+            }
+        }
+        self.fast_resolve_assets.serialize(&mut data, version)?;
+        let mut data = data.into_inner();
+        let checksum = utocmeta_checksum(data.as_slice());
+        data[UtocMetaHeader::CHECKSUM_OFFSET..UtocMetaHeader::CHECKSUM_END].copy_from_slice(&checksum.to_le_bytes());
+        stream.write_all(data.as_slice())?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use retoc::FPackageId;
+    use retoc::container_header::StoreEntry;
+    use retoc::ser::WriteExt;
+    use retoc::version::EngineVersion;
+    use crate::GenericResult;
+    use super::{MergeConflict, MergePolicy, MergeSection, UassetMetadata, UtocMetaHeader, UtocMetaImportType, UtocMetadata};
+
+    #[test]
+    fn utocmeta_round_trip() -> GenericResult<()> {
+        let version = EngineVersion::UE4_27;
+        let mut metadata = UtocMetadata::default();
+        metadata.add_graph_package_validated_entry_ue4(FPackageId(1));
+        metadata.manual_import_assets.insert(FPackageId(2), vec![FPackageId(3), FPackageId(4)]);
+        let header = UtocMetaHeader::new(version.container_header_version(), Some(version), "test");
+        let mut data = Cursor::new(vec![]);
+        metadata.serialize(&mut data, &header)?;
+        let mut read = UtocMetadata::default();
+        assert_eq!(header, read.add_from_utocmeta(data.get_ref(), version)?);
+        assert_eq!(metadata, read);
+        // Store entries for UE4 can't be read as UE5
+        assert!(UtocMetadata::default().add_from_utocmeta(data.get_ref(), EngineVersion::UE5_3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_conflicts() -> GenericResult<()> {
+        let mut first = UtocMetadata::default();
+        first.add_manual_v1_import(FPackageId(1), vec![FPackageId(2)]);
+        first.add_manual_v1_import(FPackageId(3), vec![FPackageId(4)]);
+        let mut second = UtocMetadata::default();
+        second.add_manual_v1_import(FPackageId(1), vec![FPackageId(5)]);
+        second.add_manual_v1_import(FPackageId(3), vec![FPackageId(4)]);
+        let conflicts = first.merge(second, MergePolicy::KeepExisting)?;
+        assert_eq!(conflicts, vec![MergeConflict { package_id: FPackageId(1), section: MergeSection::ManualImports }]);
+        assert_eq!(first.get_manual_v1_import(FPackageId(1)), Some([FPackageId(2)].as_slice()));
+        let mut third = UtocMetadata::default();
+        third.add_manual_v1_import(FPackageId(1), vec![FPackageId(6)]);
+        assert!(first.merge(third, MergePolicy::Fail).is_err());
+        assert_eq!(first.get_manual_v1_import(FPackageId(1)), Some([FPackageId(2)].as_slice()));
+        Ok(())
+    }
+
+    #[test]
+    fn rekey_mounted_ids() -> GenericResult<()> {
+        let store_entry = StoreEntry { export_count: 1, ..Default::default() };
+        let mut metadata = UtocMetadata::default();
+        metadata.add_from_store_entry(FPackageId(1), store_entry.clone())?;
+        metadata.add_manual_v1_import(FPackageId(2), vec![FPackageId(3)]);
+        metadata.add_graph_package_validated_entry_ue4(FPackageId(4));
+        metadata.add_from_store_entry(FPackageId(5), StoreEntry::default())?;
+        let ids = HashMap::from([(FPackageId(1), FPackageId(11)), (FPackageId(2), FPackageId(12)), (FPackageId(4), FPackageId(14))]);
+        let metadata = metadata.rekey(&ids);
+        assert_eq!(metadata.get_manual_v2_import(FPackageId(11)), Some(store_entry));
+        assert_eq!(metadata.get_manual_v2_import(FPackageId(1)), None);
+        // Imports are other packages, so only the key is changed
+        assert_eq!(metadata.get_manual_v1_import(FPackageId(12)), Some([FPackageId(3)].as_slice()));
+        assert_eq!(metadata.get_import_type(FPackageId(14)), UtocMetaImportType::GraphPackageValidated);
+        // Ids that aren't in the map are kept
+        assert_eq!(metadata.get_manual_v2_import(FPackageId(5)), Some(StoreEntry::default()));
+        Ok(())
+    }
+
+    #[test]
+    fn uassetmeta_header() -> GenericResult<()> {
+        let version = EngineVersion::UE5_4;
+        let store_entry = StoreEntry::default();
+        let mut data = Cursor::new(vec![]);
+        data.ser(&UassetMetadata::new(version.container_header_version(), FPackageId(1), store_entry.clone()))?;
+        let data = data.into_inner();
+        assert_eq!(store_entry, UassetMetadata::read(data.as_slice(), &[FPackageId(1)], version)?);
+        assert!(UassetMetadata::read(data.as_slice(), &[FPackageId(2)], version).is_err());
+        assert!(UassetMetadata::read(data.as_slice(), &[FPackageId(1)], EngineVersion::UE4_27).is_err());
+        assert!(UassetMetadata::read(&data[..data.len() - 1], &[FPackageId(1)], version).is_err());
+        // Headerless files are still read
+        let mut legacy = Cursor::new(vec![]);
+        legacy.ser(&store_entry)?;
+        assert_eq!(store_entry, UassetMetadata::read(legacy.get_ref(), &[FPackageId(1)], version)?);
+        Ok(())
+    }
+}
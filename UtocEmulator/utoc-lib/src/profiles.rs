@@ -0,0 +1,179 @@
+use retoc::container_header::EIoContainerHeaderVersion;
+use retoc::EIoStoreTocVersion;
+use retoc::version::EngineVersion;
+
+/// The root folder used when a container is mounted at ../../../ and the game isn't known
+pub const DEFAULT_ROOT_FOLDER: &'static str = "Game";
+
+/// How much a game's packages need asset metadata to have their imports resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataRequirement {
+    /// Imports can't be derived from the package (UE 5.0 - 5.2)
+    Required,
+    /// Imports can be derived from the package, but may be guessed wrong without metadata (UE4)
+    Recommended,
+    /// Packages list their own imported packages (UE 5.3+)
+    Optional
+}
+
+impl MetadataRequirement {
+    pub fn for_header_version(version: EIoContainerHeaderVersion) -> Self {
+        match version {
+            EIoContainerHeaderVersion::Initial => Self::Recommended,
+            v if v < EIoContainerHeaderVersion::NoExportInfo => Self::Required,
+            _ => Self::Optional
+        }
+    }
+}
+
+/// Everything that differs between games on the same engine version. Adding support for a game
+/// that doesn't follow it's engine version should only need a new entry in GAME_PROFILES.
+#[derive(Debug, Clone, Copy)]
+pub struct GameProfile {
+    /// The value for this profile in UTOC Emulator's EngineVersion. This is the id of the engine
+    /// version that the game reports, with the profile's number set from bit 0x10 upwards
+    pub id: u32,
+    /// Used to select the profile from the command line
+    pub name: &'static str,
+    pub display_name: &'static str,
+    /// The name of the game's project folder (e.g P3R in P3R/Content/...)
+    pub root_folder: Option<&'static str>,
+    pub engine_version: EngineVersion,
+    pub toc_version: Option<EIoStoreTocVersion>,
+    pub container_header_version: Option<EIoContainerHeaderVersion>,
+    /// Encryption key GUIDs that the game's containers use, in the same format as keys.ini
+    pub aes_key_guids: &'static [&'static str],
+    pub metadata: Option<MetadataRequirement>
+}
+
+const fn engine_id(major: u32, minor: u32) -> u32 {
+    (major << 0x8) + minor
+}
+
+const fn profile_id(engine_id: u32, profile: u32) -> u32 {
+    engine_id | profile << 0x10
+}
+
+/// Engine versions by their id in UTOC Emulator's EngineVersion. This is the only table of them on
+/// the Rust side, so it must stay in sync with EngineVersion in UTOC.Stream.Emulator.Interfaces!
+const ENGINE_VERSIONS: [(u32, EngineVersion); 11] = [
+    (engine_id(4, 25), EngineVersion::UE4_25),
+    (engine_id(4, 26), EngineVersion::UE4_26),
+    (engine_id(4, 27), EngineVersion::UE4_27),
+    (engine_id(5, 0), EngineVersion::UE5_0),
+    (engine_id(5, 1), EngineVersion::UE5_1),
+    (engine_id(5, 2), EngineVersion::UE5_2),
+    (engine_id(5, 3), EngineVersion::UE5_3),
+    (engine_id(5, 4), EngineVersion::UE5_4),
+    (engine_id(5, 5), EngineVersion::UE5_5),
+    (engine_id(5, 6), EngineVersion::UE5_6),
+    (engine_id(5, 7), EngineVersion::UE5_7),
+];
+
+/// Games that need something other than the defaults for their engine version.
+/// This must stay in sync with EngineVersion in UTOC.Stream.Emulator.Interfaces over in C# land!
+pub static GAME_PROFILES: [GameProfile; 2] = [
+    GameProfile {
+        id: profile_id(engine_id(4, 27), 1),
+        name: "P3R",
+        display_name: "Persona 3 Reload",
+        root_folder: Some("P3R"),
+        engine_version: EngineVersion::UE4_27,
+        toc_version: None,
+        container_header_version: None,
+        aes_key_guids: &[],
+        metadata: None
+    },
+    GameProfile {
+        // 4.25+ has the IO Store format from 4.26
+        id: profile_id(engine_id(4, 25), 1),
+        name: "ScarletNexus",
+        display_name: "SCARLET NEXUS",
+        root_folder: Some("ScarletNexus"),
+        engine_version: EngineVersion::UE4_26,
+        toc_version: None,
+        container_header_version: None,
+        aes_key_guids: &[],
+        metadata: None
+    },
+];
+
+impl GameProfile {
+    /// A profile with the defaults for an engine version, for games that don't have their own
+    pub fn for_engine(engine_version: EngineVersion) -> Self {
+        Self {
+            id: ENGINE_VERSIONS.iter().find(|(_, v)| *v == engine_version).map_or(0, |(id, _)| *id),
+            name: "",
+            display_name: "",
+            root_folder: None,
+            engine_version,
+            toc_version: None,
+            container_header_version: None,
+            aes_key_guids: &[],
+            metadata: None
+        }
+    }
+
+    /// Look up a profile from UTOC Emulator's EngineVersion. Plain engine versions get the defaults
+    /// for that version
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id >> 0x10 {
+            0 => ENGINE_VERSIONS.iter().find(|(v, _)| *v == id).map(|(_, v)| Self::for_engine(*v)),
+            _ => GAME_PROFILES.iter().find(|p| p.id == id).copied()
+        }
+    }
+
+    pub fn find_by_name(name: &str) -> Option<&'static Self> {
+        GAME_PROFILES.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn find_by_root_folder(folder: &str) -> Option<&'static Self> {
+        GAME_PROFILES.iter().find(|p| p.root_folder.is_some_and(|r| r.eq_ignore_ascii_case(folder)))
+    }
+
+    pub fn is_game(&self) -> bool {
+        self.id >> 0x10 != 0
+    }
+
+    pub fn toc_version(&self) -> EIoStoreTocVersion {
+        self.toc_version.unwrap_or_else(|| self.engine_version.toc_version())
+    }
+
+    pub fn container_header_version(&self) -> EIoContainerHeaderVersion {
+        self.container_header_version.unwrap_or_else(|| self.engine_version.container_header_version())
+    }
+
+    pub fn metadata_requirement(&self) -> MetadataRequirement {
+        self.metadata.unwrap_or_else(|| MetadataRequirement::for_header_version(self.container_header_version()))
+    }
+
+    pub fn root_folder(&self) -> &'static str {
+        self.root_folder.unwrap_or(DEFAULT_ROOT_FOLDER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+
+    #[test]
+    fn profile_lookup() {
+        let ids: HashSet<_> = GAME_PROFILES.iter().map(|p| p.id).collect();
+        assert_eq!(ids.len(), GAME_PROFILES.len());
+        for profile in &GAME_PROFILES {
+            assert!(profile.is_game());
+            // The base engine version has to be one the emulator knows about
+            assert!(GameProfile::from_id(profile.id & 0xffff).is_some(), "{}", profile.name);
+            assert_eq!(GameProfile::from_id(profile.id).unwrap().name, profile.name);
+        }
+        let scarlet_nexus = GameProfile::find_by_name("scarletnexus").unwrap();
+        assert_eq!(scarlet_nexus.toc_version(), EngineVersion::UE4_26.toc_version());
+        assert_eq!(GameProfile::find_by_root_folder("P3R").unwrap().engine_version, EngineVersion::UE4_27);
+        let engine = GameProfile::from_id(engine_id(5, 1)).unwrap();
+        assert!(!engine.is_game());
+        assert_eq!(engine.metadata_requirement(), MetadataRequirement::Required);
+        assert_eq!(engine.root_folder(), DEFAULT_ROOT_FOLDER);
+        assert!(GameProfile::from_id(profile_id(engine_id(4, 27), 0xff)).is_none());
+    }
+}
@@ -1,11 +1,13 @@
-use std::fs::{File, Metadata};
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs::Metadata;
+use std::io::{Read, Seek, SeekFrom};
 use anyhow::anyhow;
-use byteorder::ReadBytesExt;
+use byteorder::{LittleEndian, ReadBytesExt};
 use retoc::container_header::{EIoContainerHeaderVersion, StoreEntry};
 use retoc::{lower_utf16_cityhash, FPackageId};
 use retoc::ser::ReadExt;
-use retoc::zen::{ExternalPackageDependency, FExportBundleEntry, FExportBundleHeader, FExportMapEntry, FInternalDependencyArc, FZenPackageImportedPackageNamesContainer, FZenPackageSummary, FZenPackageVersioningInfo};
+use retoc::zen::{ExternalPackageDependency, FExportBundleEntry, FExportMapEntry, FInternalDependencyArc, FZenPackageImportedPackageNamesContainer, FZenPackageSummary, FZenPackageVersioningInfo};
 use crate::assets::AssetEntry;
 use crate::GenericResult;
 use crate::metadata::UtocMetadata;
@@ -20,6 +22,13 @@ pub trait MetadataProvider {
         package_header: &FZenPackageSummary,
         package_dependencies: &[ExternalPackageDependency],
     );
+    /// Whether UE 5.0 - 5.2 packages without metadata should have their imports guessed with
+    /// HeuristicImportResolver instead of failing to build
+    fn allow_heuristic_imports(&self) -> bool {
+        false
+    }
+    /// Called with the result for each package that HeuristicImportResolver was used for
+    fn report_heuristic_imports(&self, _package_id: FPackageId, _imports: &HeuristicImports) {}
 }
 
 pub fn size_of_export_bundle_header_ue4(header_version: EIoContainerHeaderVersion) -> u32 {
@@ -29,20 +38,91 @@ pub fn size_of_export_bundle_header_ue4(header_version: EIoContainerHeaderVersio
     }
 }
 
+/// UE4 ONLY. Count the export bundles in the section from start to end (the summary's export bundle
+/// and graph data offsets), which has every FExportBundleHeader followed by the entries for each of
+/// them. The number of bundles isn't stored in the package, but each header's first entry index is
+/// the sum of the entries before it, so there's only one count where the headers and entries fill
+/// the section exactly. Each export must have one Create and one Serialize entry
+pub fn read_legacy_export_bundles<T: Read + Seek>(reader: &mut T, export_count: u32, start: u64, end: u64) -> GenericResult<u32> {
+    let header_size = size_of_export_bundle_header_ue4(EIoContainerHeaderVersion::Initial) as u64;
+    let entry_size = size_of::<FExportBundleEntry>() as u64;
+    if end < start || (end - start) % entry_size != 0 {
+        return Err(anyhow!("Export bundles from 0x{:X} to 0x{:X} aren't a whole number of entries", start, end).into_boxed_dyn_error());
+    }
+    reader.seek(SeekFrom::Start(start))?;
+    let (mut bundles, mut entries) = (0u64, 0u64);
+    while bundles * header_size + entries * entry_size < end - start {
+        let first_entry_index = reader.read_u32::<LittleEndian>()?;
+        let entry_count = reader.read_u32::<LittleEndian>()?;
+        if first_entry_index as u64 != entries || entry_count == 0 {
+            return Err(anyhow!("Export bundle {} starts at entry {} with {} entries, expected it to start at entry {}",
+                bundles, first_entry_index, entry_count, entries).into_boxed_dyn_error());
+        }
+        bundles += 1;
+        entries += entry_count as u64;
+    }
+    if bundles * header_size + entries * entry_size != end - start {
+        return Err(anyhow!("{} export bundles with {} entries don't fit in the 0x{:X} bytes before the graph data",
+            bundles, entries, end - start).into_boxed_dyn_error());
+    }
+    // Checked before allocating, since export_count comes from the summary's offsets
+    if export_count as u64 > entries {
+        return Err(anyhow!("The package has {} exports, but there are only {} export bundle entries",
+            export_count, entries).into_boxed_dyn_error());
+    }
+    // EExportCommandType: Create = 0, Serialize = 1
+    let mut commands = vec![0u8; export_count as usize];
+    for i in 0..entries {
+        let local_export_index = reader.read_u32::<LittleEndian>()?;
+        let command_type = reader.read_u32::<LittleEndian>()?;
+        let command = match (commands.get_mut(local_export_index as usize), command_type) {
+            (Some(command), 0 | 1) => command,
+            _ => return Err(anyhow!("Export bundle entry {} has export {} and command {}, but the package has {} exports",
+                i, local_export_index, command_type, export_count).into_boxed_dyn_error())
+        };
+        if *command & (1 << command_type) != 0 {
+            return Err(anyhow!("Export {} has more than one {} command", local_export_index,
+                if command_type == 0 { "Create" } else { "Serialize" }).into_boxed_dyn_error());
+        }
+        *command |= 1 << command_type;
+    }
+    if let Some(export) = commands.iter().position(|c| *c != 0b11) {
+        return Err(anyhow!("Export {} doesn't have both a Create and Serialize command", export).into_boxed_dyn_error());
+    }
+    Ok(bundles as u32)
+}
+
 pub trait StoreEntryBuilder {
+    /// Build the store entry for a package that's size bytes long from any reader, such as a
+    /// Cursor over a package that's already in memory
+    fn read_store_entry<T: MetadataProvider, R: Read + Seek>(
+        reader: &mut R,
+        size: u64,
+        package_id: FPackageId,
+        header_version: EIoContainerHeaderVersion,
+        metadata_provider: &T
+    ) -> GenericResult<StoreEntry>;
+
+    /// Same as read_store_entry, but the asset is only opened if it doesn't have a manual v2 import
     fn rebuild_store_entry<T: MetadataProvider>(
         asset_entry: &AssetEntry,
         package_id: FPackageId,
         header_version: EIoContainerHeaderVersion,
         metadata_provider: &T
-    ) -> GenericResult<StoreEntry>;
+    ) -> GenericResult<StoreEntry> {
+        if let Some(store) = metadata_provider.check_v2_import(package_id) {
+            return Ok(store);
+        }
+        Self::read_store_entry(&mut asset_entry.open()?, asset_entry.size, package_id, header_version, metadata_provider)
+    }
 }
 
 pub struct StoreEntryBuilderOld;
 
 impl StoreEntryBuilder for StoreEntryBuilderOld {
-    fn rebuild_store_entry<T: MetadataProvider>(
-        asset_entry: &AssetEntry,
+    fn read_store_entry<T: MetadataProvider, R: Read + Seek>(
+        reader: &mut R,
+        size: u64,
         package_id: FPackageId,
         header_version: EIoContainerHeaderVersion,
         metadata_provider: &T
@@ -50,39 +130,17 @@ impl StoreEntryBuilder for StoreEntryBuilderOld {
         if let Some(store) = metadata_provider.check_v2_import(package_id) {
             return Ok(store);
         }
-        let mut reader = BufReader::with_capacity(
-            0x2000, File::open(asset_entry.os_path.as_path())?);
         let mut store_entry = StoreEntry::default();
         let package_header = FZenPackageSummary::deserialize(
-            &mut reader, header_version)?;
-        store_entry.export_count = (package_header.export_bundle_entries_offset
-            - package_header.export_map_offset) / size_of::<FExportMapEntry>() as i32;
-        // Go through each export bundle to look for the highest index
-        reader.seek(SeekFrom::Start(package_header.export_bundle_entries_offset as u64))?;
-        let mut predicted_export_bundles: Vec<FExportBundleHeader> = vec![];
-        loop {
-            let new_entry = FExportBundleHeader::deserialize(&mut reader, header_version)?;
-            if new_entry.entry_count == 0 {
-                break;
-            }
-            if let Some(last) = predicted_export_bundles.last()
-                && new_entry.first_entry_index != last.entry_count {
-                break;
-            }
-            predicted_export_bundles.push(new_entry);
-        }
-        let export_bundle_bytes = (package_header.graph_data_offset - package_header.export_bundle_entries_offset) as u32;
-        let actual_entries = (export_bundle_bytes - predicted_export_bundles.len() as u32 * size_of_export_bundle_header_ue4(header_version))
-            / size_of_export_bundle_header_ue4(header_version);
-        let mut export_bundle_count = predicted_export_bundles.len();
-        loop {
-            if export_bundle_count == 0 || actual_entries == predicted_export_bundles[..export_bundle_count]
-                .iter().map(|v| v.entry_count).sum::<u32>() {
-                break;
-            }
-            export_bundle_count -= 1;
+            reader, header_version)?;
+        let export_map_size = package_header.export_bundle_entries_offset as i64 - package_header.export_map_offset as i64;
+        if export_map_size < 0 {
+            return Err(anyhow!("The export map at 0x{:X} starts after the export bundles at 0x{:X}",
+                package_header.export_map_offset, package_header.export_bundle_entries_offset).into_boxed_dyn_error());
         }
-        store_entry.export_bundle_count = export_bundle_count.max(1) as i32;
+        store_entry.export_count = (export_map_size / size_of::<FExportMapEntry>() as i64) as i32;
+        store_entry.export_bundle_count = read_legacy_export_bundles(reader, store_entry.export_count as u32,
+            package_header.export_bundle_entries_offset as u64, package_header.graph_data_offset as u64)? as i32;
 
         reader.seek(SeekFrom::Start(package_header.graph_data_offset as u64))?;
         let mut package_dependencies: Vec<ExternalPackageDependency> = vec![];
@@ -98,12 +156,12 @@ impl StoreEntryBuilder for StoreEntryBuilderOld {
         }
         metadata_provider.get_imports_ue4(
             &mut store_entry,
-            &mut reader,
+            reader,
             package_id,
             &package_header,
             package_dependencies.as_slice()
         );
-        store_entry.export_bundles_size = asset_entry.size;
+        store_entry.export_bundles_size = size;
         Ok(store_entry)
     }
 }
@@ -111,8 +169,9 @@ impl StoreEntryBuilder for StoreEntryBuilderOld {
 pub struct StoreEntryBuilderNew;
 
 impl StoreEntryBuilder for StoreEntryBuilderNew {
-    fn rebuild_store_entry<T: MetadataProvider>(
-        asset_entry: &AssetEntry,
+    fn read_store_entry<T: MetadataProvider, R: Read + Seek>(
+        reader: &mut R,
+        size: u64,
         package_id: FPackageId,
         header_version: EIoContainerHeaderVersion,
         metadata_provider: &T
@@ -120,19 +179,23 @@ impl StoreEntryBuilder for StoreEntryBuilderNew {
         if let Some(store) = metadata_provider.check_v2_import(package_id) {
             return Ok(store);
         }
-        if header_version < EIoContainerHeaderVersion::NoExportInfo {
+        let heuristic = header_version < EIoContainerHeaderVersion::NoExportInfo;
+        if heuristic && !metadata_provider.allow_heuristic_imports() {
             return Err(anyhow!("Asset metadata is required for UE5 versions before 5.3!").into_boxed_dyn_error());
         }
-        let mut reader = BufReader::with_capacity(
-            0x2000, File::open(asset_entry.os_path.as_path())?);
         let mut store_entry = StoreEntry::default();
 
         /// From retoc:
         /// https://github.com/trumank/retoc/blob/master/retoc/src/zen.rs#L871
         let summary = FZenPackageSummary::deserialize(
-            &mut reader, header_version)?;
+            reader, header_version)?;
         let _: Option<FZenPackageVersioningInfo> = // optional versioning info
             if summary.has_versioning_info != 0 { Some(reader.de()?) } else { None };
+        // The name map follows the versioning info, so it has to be read before anything else is
+        let names = match heuristic {
+            true => HeuristicImportResolver::read_name_map(reader)?,
+            false => vec![]
+        };
         // For UE PackageVersion >= EUnrealEngineObjectUE5Version::VERSE_CELLS is checked here, however at this point we do not know the package file version for the package
         // We do know the container header version though, and VERSE_CELLS is introduced as a part of UE 5.6, which ships with header_version == EIoContainerHeaderVersion::SoftPackageReferencesOffset
         // so we can check for that instead and get the correct result without having to know the engine version at this point
@@ -154,6 +217,15 @@ impl StoreEntryBuilder for StoreEntryBuilderNew {
                 store_entry.export_count
             ).into_boxed_dyn_error());
         }
+        if heuristic {
+            let imports = HeuristicImportResolver::resolve(reader, &summary, package_id,
+                names.as_slice(), store_entry.export_bundle_count as u32)?;
+            metadata_provider.report_heuristic_imports(package_id, &imports);
+            store_entry.export_bundle_count = imports.export_bundle_count;
+            store_entry.imported_packages = imports.imported_packages;
+            store_entry.export_bundles_size = size;
+            return Ok(store_entry);
+        }
         let mut imported_package_names: FZenPackageImportedPackageNamesContainer = FZenPackageImportedPackageNamesContainer::default();
         if summary.imported_package_names_offset > 0 {
             reader.seek(SeekFrom::Start(summary.imported_package_names_offset as u64))?;
@@ -163,7 +235,7 @@ impl StoreEntryBuilder for StoreEntryBuilderNew {
         // ImportedPackageNames is required for this to work, which is true for UE 5.3+
         store_entry.imported_packages = imported_package_names.imported_package_names.iter()
             .map(|x| FPackageId::from_name(x)).collect();
-        store_entry.export_bundles_size = asset_entry.size;
+        store_entry.export_bundles_size = size;
         Ok(store_entry)
     }
 }
@@ -199,22 +271,24 @@ impl LegacyImportIdResolver {
         dependencies.iter().map(|d| d.from_package_id).collect()
     }
 
+    /// Unreal stores the number of an FName (the 2 in /Game/Foo_2) separately from it's string, so the
+    /// name map only has /Game/Foo. UE4 zen packages don't keep the FNames that referred to it (the
+    /// import map is hashed object paths), so suffixes are tried until one hashes to a dependency.
+    /// Numbers past this aren't found
+    const MAX_NAME_NUMBER: u32 = 1024;
+
     /// From graph package imports, but with validating the name entries for matching file names
     /// Not all files actually have the same graph package imports as container header imports (Unreal shenanigans)
-    /// However, there's an edge case with files ending in an underscore + number, where Unreal won't serialize that
-    /// last portion of the string. I'd need an external tool to fix this
     pub fn from_graph_packages_validated<T: Read + Seek>(reader: &mut T, summary: &FZenPackageSummary,
+                                                     package_id: FPackageId,
                                                      dependencies: &[ExternalPackageDependency]) -> Vec<FPackageId> {
         reader.seek(SeekFrom::Start(summary.name_map_names_offset as u64)).unwrap();
         let name_count = (summary.name_map_names_size as usize / size_of::<u64>()) - 1;
         let names: Vec<String> = (0..name_count).filter_map(|_| from_buffer_text(reader).ok()).collect();
-        let mut path_name_hashes = vec![];
-        loop { // we only want to hash file paths, which Unreal always serializes at the beginning
-            if path_name_hashes.len() == names.len() || !names[path_name_hashes.len()].starts_with("/") {
-                break;
-            }
-            path_name_hashes.push(lower_utf16_cityhash(&names[path_name_hashes.len()]));
-        }
+        // we only want to hash file paths, which Unreal always serializes at the beginning
+        let path_names: Vec<&str> = names.iter().map(|n| n.as_str()).take_while(|n| n.starts_with("/")).collect();
+        let path_name_hashes = Self::hash_path_names(path_names.as_slice(), package_id,
+            dependencies.iter().map(|d| d.from_package_id));
         dependencies.iter()
             .filter_map(|d| {
                 match path_name_hashes.contains(&d.from_package_id.0) {
@@ -225,6 +299,39 @@ impl LegacyImportIdResolver {
             .collect()
     }
 
+    /// Hash each path name, adding number suffixes back onto package names that aren't the package or a
+    /// dependency as they are. Object paths (soft references) and script packages are never numbered imports
+    fn hash_path_names(path_names: &[&str], package_id: FPackageId,
+        package_ids: impl Iterator<Item = FPackageId>) -> HashSet<u64> {
+        let mut remaining: HashSet<u64> = package_ids.map(|id| id.0).collect();
+        let mut hashes = HashSet::new();
+        let mut unmatched = vec![];
+        for name in path_names {
+            let hash = lower_utf16_cityhash(name);
+            hashes.insert(hash);
+            if !remaining.remove(&hash) && hash != package_id.0
+                && !name.contains(['.', ':']) && !name.starts_with("/Script/") {
+                unmatched.push(*name);
+            }
+        }
+        let mut candidate = String::new();
+        // FName number N is written as _{N - 1}
+        for number in 0..Self::MAX_NAME_NUMBER {
+            if remaining.is_empty() || unmatched.is_empty() {
+                break;
+            }
+            for name in &unmatched {
+                candidate.clear();
+                write!(candidate, "{}_{}", name, number).unwrap();
+                let hash = lower_utf16_cityhash(&candidate);
+                if remaining.remove(&hash) {
+                    hashes.insert(hash);
+                }
+            }
+        }
+        hashes
+    }
+
     /// If required, import ids can be manually specified from the metadata file. Trying to generate a
     /// UCAS file with no external metadata was always going to be a challenge
     pub fn from_metadata_v1(meta: &UtocMetadata, asset: FPackageId) -> Vec<FPackageId> {
@@ -233,6 +340,196 @@ impl LegacyImportIdResolver {
     }
 }
 
+/// How far a store entry from HeuristicImportResolver can be trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportConfidence {
+    /// The package imports at most one other package, so there's only one order they could be in
+    Exact,
+    /// There's one package name in the name map for each imported package. They're put in the same
+    /// order as the cooker puts them (sorted by package id), which is only right if every one of those
+    /// names is an import rather than a soft reference
+    Probable
+}
+
+/// A store entry derived from a UE 5.0 - 5.2 package without any metadata
+#[derive(Debug, Clone)]
+pub struct HeuristicImports {
+    pub imported_packages: Vec<FPackageId>,
+    /// The names that imported_packages were hashed from
+    pub package_names: Vec<String>,
+    pub export_bundle_count: i32,
+    pub confidence: ImportConfidence
+}
+
+/// UE 5.0 - 5.2 ONLY. These packages don't say which packages they import, only how many (there's a
+/// list of external arcs for each imported package in the graph data) and indices into the store
+/// entry's imported packages (from the import map). Cooked packages keep their whole name map for the
+/// export data, which includes the name of every imported package, so the imports can be guessed when
+/// the name map has exactly one package name for each of them.
+#[derive(Debug)]
+pub struct HeuristicImportResolver;
+
+/// The parts of FZenPackageSummary that HeuristicImportResolver reads
+#[derive(Debug, Clone, Copy)]
+struct HeuristicHeaderOffsets {
+    import_map: u64,
+    export_map: u64,
+    graph_data: u64,
+    header_end: u64
+}
+
+impl From<&FZenPackageSummary> for HeuristicHeaderOffsets {
+    fn from(summary: &FZenPackageSummary) -> Self {
+        Self {
+            import_map: summary.import_map_offset as u64,
+            export_map: summary.export_map_offset as u64,
+            graph_data: summary.graph_data_offset as u64,
+            header_end: summary.header_size as u64
+        }
+    }
+}
+
+impl HeuristicImportResolver {
+    // FPackageObjectIndex
+    const TYPE_SHIFT: u64 = 62;
+    const TYPE_PACKAGE_IMPORT: u64 = 2;
+    const IMPORTED_PACKAGE_INDEX_MASK: u64 = 0x3fff_ffff;
+    // FExternalArc: FromImportIndex (i32), FromCommandType (u8), ToExportBundleIndex (i32)
+    const EXTERNAL_ARC_SIZE: i64 = 9;
+    // FInternalArc: FromExportBundleIndex (i32), ToExportBundleIndex (i32)
+    const INTERNAL_ARC_SIZE: i64 = 8;
+
+    /// Read a name batch (FNameMap) from the package header. Unlike the UE4 name map, the lengths of
+    /// each name are stored together before the string data
+    pub fn read_name_map<T: Read + Seek>(reader: &mut T) -> GenericResult<Vec<String>> {
+        let count = reader.read_u32::<LittleEndian>()?;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let _string_bytes = reader.read_u32::<LittleEndian>()?;
+        let _hash_version = reader.read_u64::<LittleEndian>()?;
+        reader.seek(SeekFrom::Current(count as i64 * size_of::<u64>() as i64))?; // hashes
+        let mut headers = vec![0; count as usize * 2];
+        reader.read_exact(&mut headers)?;
+        headers.chunks_exact(2).map(|header| {
+            let len = ((header[0] as usize & 0x7f) << 8) | header[1] as usize;
+            Ok(match header[0] & 0x80 != 0 {
+                true => {
+                    let mut buf = vec![0; len * 2];
+                    reader.read_exact(&mut buf)?;
+                    String::from_utf16_lossy(&buf.chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>())
+                },
+                false => {
+                    let mut buf = vec![0; len];
+                    reader.read_exact(&mut buf)?;
+                    String::from_utf8_lossy(&buf).into_owned()
+                }
+            })
+        }).collect()
+    }
+
+    /// Count the export bundles, then the imported packages, from the graph data. Returns an error if
+    /// the graph data doesn't end at the end of the package header
+    fn read_graph_data<T: Read + Seek>(reader: &mut T, offsets: &HeuristicHeaderOffsets,
+        export_bundle_entry_count: u32) -> GenericResult<(i32, usize)> {
+        reader.seek(SeekFrom::Start(offsets.graph_data))?;
+        // Each FExportBundleHeader starts where the previous one's entries ended
+        let (mut export_bundle_count, mut entries) = (0, 0);
+        while entries < export_bundle_entry_count {
+            let _serial_offset = reader.read_u64::<LittleEndian>()?;
+            let first_entry_index = reader.read_u32::<LittleEndian>()?;
+            let entry_count = reader.read_u32::<LittleEndian>()?;
+            if first_entry_index != entries || entry_count == 0 {
+                return Err(anyhow!("Export bundle {} starts at entry {} with {} entries, expected it to start at entry {}",
+                    export_bundle_count, first_entry_index, entry_count, entries).into_boxed_dyn_error());
+            }
+            entries += entry_count;
+            export_bundle_count += 1;
+        }
+        if entries != export_bundle_entry_count {
+            return Err(anyhow!("Export bundles have {} entries, but the package has {}", entries, export_bundle_entry_count).into_boxed_dyn_error());
+        }
+        let internal_arcs = reader.read_i32::<LittleEndian>()?;
+        reader.seek(SeekFrom::Current(internal_arcs.max(0) as i64 * Self::INTERNAL_ARC_SIZE))?;
+        let header_end = offsets.header_end;
+        let mut imported_packages = 0;
+        while reader.stream_position()? < header_end {
+            let external_arcs = reader.read_i32::<LittleEndian>()?;
+            if external_arcs < 0 {
+                return Err(anyhow!("Imported package {} has {} external arcs", imported_packages, external_arcs).into_boxed_dyn_error());
+            }
+            reader.seek(SeekFrom::Current(external_arcs as i64 * Self::EXTERNAL_ARC_SIZE))?;
+            imported_packages += 1;
+        }
+        if reader.stream_position()? != header_end {
+            return Err(anyhow!("Graph data ends at 0x{:X}, past the end of the package header (0x{:X})",
+                reader.stream_position()?, header_end).into_boxed_dyn_error());
+        }
+        Ok((export_bundle_count, imported_packages))
+    }
+
+    /// The number of imported packages that the import map refers to
+    fn read_import_map<T: Read + Seek>(reader: &mut T, offsets: &HeuristicHeaderOffsets) -> GenericResult<usize> {
+        reader.seek(SeekFrom::Start(offsets.import_map))?;
+        let count = (offsets.export_map - offsets.import_map) as usize / size_of::<u64>();
+        let mut referenced = 0;
+        for _ in 0..count {
+            let import = reader.read_u64::<LittleEndian>()?;
+            if import >> Self::TYPE_SHIFT == Self::TYPE_PACKAGE_IMPORT {
+                referenced = referenced.max(((import >> 32) & Self::IMPORTED_PACKAGE_INDEX_MASK) as usize + 1);
+            }
+        }
+        Ok(referenced)
+    }
+
+    /// Names that could be one of the package's imports. Script packages aren't included in the store
+    /// entry, and names with a '.' or ':' are object paths
+    fn get_package_names(names: &[String], package_id: FPackageId) -> Vec<String> {
+        names.iter()
+            .filter(|n| n.starts_with('/') && !n.starts_with("/Script/") && !n.contains(['.', ':'])
+                && FPackageId::from_name(n) != package_id)
+            .cloned().collect()
+    }
+
+    pub fn resolve<T: Read + Seek>(reader: &mut T, summary: &FZenPackageSummary, package_id: FPackageId,
+        names: &[String], export_bundle_entry_count: u32) -> GenericResult<HeuristicImports> {
+        Self::resolve_offsets(reader, &summary.into(), package_id, names, export_bundle_entry_count)
+    }
+
+    fn resolve_offsets<T: Read + Seek>(reader: &mut T, offsets: &HeuristicHeaderOffsets, package_id: FPackageId,
+        names: &[String], export_bundle_entry_count: u32) -> GenericResult<HeuristicImports> {
+        let referenced = Self::read_import_map(reader, offsets)?;
+        let (export_bundle_count, import_count) = Self::read_graph_data(reader, offsets, export_bundle_entry_count)?;
+        if referenced > import_count {
+            return Err(anyhow!("The import map refers to imported package {}, but the graph data only has {} imported packages",
+                referenced - 1, import_count).into_boxed_dyn_error());
+        }
+        let package_names = match import_count {
+            // Anything left in the name map is a soft reference
+            0 => vec![],
+            _ => Self::get_package_names(names, package_id)
+        };
+        if package_names.len() != import_count {
+            return Err(anyhow!("Can't guess the imports for this package without metadata: it imports {} packages, but the name map has {} package names ({})",
+                import_count, package_names.len(), package_names.join(", ")).into_boxed_dyn_error());
+        }
+        // The cooker sorts each package's imported package ids (FPackageStoreOptimizer::ProcessImports),
+        // and the import map's indices are into that sorted list
+        let mut imports: Vec<_> = package_names.into_iter().map(|n| (FPackageId::from_name(&n), n)).collect();
+        imports.sort_by_key(|(id, _)| id.0);
+        let (imported_packages, package_names) = imports.into_iter().unzip();
+        Ok(HeuristicImports {
+            imported_packages,
+            package_names,
+            export_bundle_count,
+            confidence: match import_count {
+                0 | 1 => ImportConfidence::Exact,
+                _ => ImportConfidence::Probable
+            }
+        })
+    }
+}
 
 #[cfg(target_os = "linux")]
 pub fn os_file_size(metadata: &Metadata) -> u64 {
@@ -242,4 +539,280 @@ pub fn os_file_size(metadata: &Metadata) -> u64 {
 #[cfg(target_os = "windows")]
 pub fn os_file_size(metadata: &Metadata) -> u64 {
     std::os::windows::fs::MetadataExt::file_size(metadata)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use retoc::FPackageId;
+    use std::io::{Read, Seek};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use retoc::container_header::{EIoContainerHeaderVersion, StoreEntry};
+    use retoc::zen::{ExternalPackageDependency, FExportMapEntry, FZenPackageSummary};
+    use crate::assets::AssetEntry;
+    use super::{read_legacy_export_bundles, HeuristicHeaderOffsets, HeuristicImportResolver, ImportConfidence,
+        LegacyImportIdResolver, MetadataProvider, StoreEntryBuilder, StoreEntryBuilderOld};
+
+    /// Export bundle headers followed by their entries, as pairs of u32s
+    fn export_bundles(headers: &[(u32, u32)], entries: &[(u32, u32)]) -> Cursor<Vec<u8>> {
+        Cursor::new(headers.iter().chain(entries)
+            .flat_map(|(a, b)| a.to_le_bytes().into_iter().chain(b.to_le_bytes()))
+            .collect())
+    }
+
+    fn count(headers: &[(u32, u32)], entries: &[(u32, u32)], export_count: u32) -> Result<u32, String> {
+        let mut reader = export_bundles(headers, entries);
+        let end = reader.get_ref().len() as u64;
+        read_legacy_export_bundles(&mut reader, export_count, 0, end).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn legacy_export_bundles() {
+        // One bundle per export
+        assert_eq!(count(&[(0, 2), (2, 2)], &[(0, 0), (0, 1), (1, 0), (1, 1)], 2), Ok(2));
+        // Blueprints create every export before serializing any of them
+        assert_eq!(count(&[(0, 6)], &[(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)], 3), Ok(1));
+        assert_eq!(count(&[], &[], 0), Ok(0));
+    }
+
+    #[test]
+    fn legacy_export_bundle_errors() {
+        // Headers that don't follow on from each other
+        assert!(count(&[(0, 2), (0, 2)], &[(0, 0), (0, 1), (1, 0), (1, 1)], 2).is_err());
+        // An entry missing from the end of the section
+        assert!(count(&[(0, 2), (2, 2)], &[(0, 0), (0, 1), (1, 0)], 2).is_err());
+        // Missing Serialize
+        assert!(count(&[(0, 2)], &[(0, 0), (1, 0)], 2).unwrap_err().contains("Export 0"));
+        // Export index past the export map
+        assert!(count(&[(0, 2)], &[(0, 0), (2, 1)], 2).is_err());
+        // More exports than there are entries, which is checked before anything is allocated for them
+        assert!(count(&[(0, 2)], &[(0, 0), (0, 1)], u32::MAX).unwrap_err().contains("only 2 export bundle entries"));
+        // Half of an entry
+        let mut reader = export_bundles(&[(0, 2)], &[(0, 0), (0, 1)]);
+        assert!(read_legacy_export_bundles(&mut reader, 1, 0, 20).is_err());
+    }
+
+    /// Takes every graph package as an import, like packages without metadata
+    struct GraphPackages;
+
+    impl MetadataProvider for GraphPackages {
+        fn check_v2_import(&self, _package_id: FPackageId) -> Option<StoreEntry> {
+            None
+        }
+        fn get_imports_ue4<T: Read + Seek>(&self, store_entry: &mut StoreEntry, _reader: &mut T, _package_id: FPackageId,
+            _package_header: &FZenPackageSummary, package_dependencies: &[ExternalPackageDependency]) {
+            store_entry.imported_packages = LegacyImportIdResolver::from_graph_packages_unvalidated(package_dependencies);
+        }
+    }
+
+    /// A UE4 zen package header: the package summary, an empty name map and import map, export_count
+    /// exports, the export bundles and then graph data with (package name, internal arc count) for
+    /// each imported package
+    fn legacy_package(export_count: usize, headers: &[(u32, u32)], entries: &[(u32, u32)],
+        imports: &[(&str, u32)]) -> AssetEntry {
+        const SUMMARY_SIZE: usize = 64;
+        let export_map = SUMMARY_SIZE;
+        let bundles = export_map + export_count * size_of::<FExportMapEntry>();
+        let bundle_data = export_bundles(headers, entries).into_inner();
+        let graph_data = bundles + bundle_data.len();
+        let mut graph = vec![];
+        graph.extend((imports.len() as i32).to_le_bytes());
+        for (name, arcs) in imports {
+            graph.extend(FPackageId::from_name(name).0.to_le_bytes());
+            graph.extend(arcs.to_le_bytes());
+            graph.extend(vec![0; *arcs as usize * 8]);
+        }
+        let mut data = vec![0u8; 16]; // Name and SourceName
+        // PackageFlags, CookedHeaderSize, NameMapNamesOffset/Size, NameMapHashesOffset/Size,
+        // ImportMapOffset, ExportMapOffset, ExportBundlesOffset, GraphDataOffset, GraphDataSize, Pad
+        for value in [0, graph_data + graph.len(), export_map, 0, export_map, 0,
+            export_map, export_map, bundles, graph_data, graph.len(), 0] {
+            data.extend((value as u32).to_le_bytes());
+        }
+        assert_eq!(data.len(), SUMMARY_SIZE);
+        data.resize(bundles, 0);
+        data.extend(bundle_data);
+        data.extend(graph);
+        AssetEntry::from_memory(PathBuf::from("Test/Content/Package.uasset"), Arc::from(data))
+    }
+
+    fn rebuild(asset: &AssetEntry) -> crate::GenericResult<StoreEntry> {
+        StoreEntryBuilderOld::rebuild_store_entry(asset, FPackageId::from_name("/Game/Package"),
+            EIoContainerHeaderVersion::Initial, &GraphPackages)
+    }
+
+    #[test]
+    fn legacy_store_entry() -> crate::GenericResult<()> {
+        let asset = legacy_package(2, &[(0, 2), (2, 2)], &[(0, 0), (0, 1), (1, 0), (1, 1)],
+            &[("/Game/A", 1), ("/Game/B", 0)]);
+        let store_entry = rebuild(&asset)?;
+        assert_eq!(store_entry.export_count, 2);
+        assert_eq!(store_entry.export_bundle_count, 2);
+        assert_eq!(store_entry.imported_packages, ["/Game/A", "/Game/B"].map(FPackageId::from_name));
+        assert_eq!(store_entry.export_bundles_size, asset.size);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_store_entry_blueprint() -> crate::GenericResult<()> {
+        // Blueprints have a single bundle that creates every export before serializing them
+        let asset = legacy_package(3, &[(0, 6)], &[(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)],
+            &[("/Game/Parent", 2)]);
+        let store_entry = rebuild(&asset)?;
+        assert_eq!(store_entry.export_count, 3);
+        assert_eq!(store_entry.export_bundle_count, 1);
+        assert_eq!(store_entry.imported_packages, [FPackageId::from_name("/Game/Parent")]);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_store_entry_inconsistent() {
+        // Three exports in the export map, but the bundles only create and serialize two of them
+        let asset = legacy_package(3, &[(0, 4)], &[(0, 0), (1, 0), (0, 1), (1, 1)], &[]);
+        assert!(rebuild(&asset).unwrap_err().to_string().contains("Export 2"));
+        // An export map that starts after the export bundles would have a negative number of exports
+        let asset = legacy_package(1, &[(0, 2)], &[(0, 0), (0, 1)], &[]);
+        let mut data = asset.data.unwrap().to_vec();
+        let export_bundles = u32::from_le_bytes(data[48..52].try_into().unwrap());
+        data[44..48].copy_from_slice(&(export_bundles + 8).to_le_bytes()); // ExportMapOffset
+        let asset = AssetEntry::from_memory(asset.os_path, Arc::from(data));
+        assert!(rebuild(&asset).unwrap_err().to_string().contains("starts after the export bundles"));
+    }
+
+    #[test]
+    fn path_name_numbers() {
+        let ids = ["/Game/Baz", "/Game/Foo_1", "/Game/Foo_12", "/Game/Bar_0", "/Game/Missing"]
+            .map(FPackageId::from_name);
+        let own_id = FPackageId::from_name("/Game/Self");
+        let hashes = LegacyImportIdResolver::hash_path_names(
+            &["/Game/Self", "/Game/Baz", "/Game/Foo", "/Game/Bar"], own_id, ids.into_iter());
+        assert!(ids[..4].iter().all(|id| hashes.contains(&id.0)));
+        assert!(!hashes.contains(&ids[4].0));
+        // Soft references are object paths, so they're never tried with a number
+        let id = FPackageId::from_name("/Game/Soft.Soft_0");
+        assert!(!LegacyImportIdResolver::hash_path_names(&["/Game/Soft.Soft"], own_id, [id].into_iter()).contains(&id.0));
+        // Names that are already a dependency aren't numbered
+        let ids = ["/Game/Foo", "/Game/Foo_1"].map(FPackageId::from_name);
+        let hashes = LegacyImportIdResolver::hash_path_names(&["/Game/Foo"], own_id, ids.into_iter());
+        assert!(hashes.contains(&ids[0].0) && !hashes.contains(&ids[1].0));
+        // Numbers past MAX_NAME_NUMBER
+        let id = FPackageId::from_name(&format!("/Game/Foo_{}", LegacyImportIdResolver::MAX_NAME_NUMBER));
+        assert!(!LegacyImportIdResolver::hash_path_names(&["/Game/Foo"], own_id, [id].into_iter()).contains(&id.0));
+    }
+
+    /// FNameMap with ASCII names, except for names starting with ! which are written as UTF-16
+    fn name_batch(names: &[&str]) -> Cursor<Vec<u8>> {
+        let strings: Vec<(bool, Vec<u8>)> = names.iter().map(|n| match n.strip_prefix('!') {
+            Some(wide) => (true, wide.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            None => (false, n.as_bytes().to_vec())
+        }).collect();
+        let mut data = vec![];
+        data.extend((names.len() as u32).to_le_bytes());
+        data.extend((strings.iter().map(|(_, s)| s.len()).sum::<usize>() as u32).to_le_bytes());
+        data.extend(0u64.to_le_bytes()); // hash version
+        data.extend(vec![0; names.len() * 8]); // hashes
+        for (wide, string) in &strings {
+            let len = if *wide { string.len() / 2 } else { string.len() };
+            data.extend([(len >> 8) as u8 | if *wide { 0x80 } else { 0 }, len as u8]);
+        }
+        strings.into_iter().for_each(|(_, s)| data.extend(s));
+        Cursor::new(data)
+    }
+
+    fn package_import(index: u64) -> u64 {
+        2 << 62 | index << 32
+    }
+
+    /// An import map followed by the graph data, with an export bundle header for each (first entry,
+    /// entry count) and an imported package for each external arc count
+    fn graph_data(imports: &[u64], bundles: &[(u32, u32)], external_arcs: &[i32]) -> (Cursor<Vec<u8>>, HeuristicHeaderOffsets) {
+        let mut data: Vec<u8> = imports.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let graph_data = data.len() as u64;
+        for (first, count) in bundles {
+            data.extend(0u64.to_le_bytes()); // serial offset
+            data.extend(first.to_le_bytes());
+            data.extend(count.to_le_bytes());
+        }
+        data.extend(1i32.to_le_bytes());
+        data.extend([0; 8]); // one internal arc
+        for arcs in external_arcs {
+            data.extend(arcs.to_le_bytes());
+            data.extend(vec![0; *arcs as usize * 9]);
+        }
+        let offsets = HeuristicHeaderOffsets { import_map: 0, export_map: graph_data, graph_data, header_end: data.len() as u64 };
+        (Cursor::new(data), offsets)
+    }
+
+    fn resolve(names: &[&str], imports: &[u64], external_arcs: &[i32]) -> Result<super::HeuristicImports, String> {
+        let (mut reader, offsets) = graph_data(imports, &[(0, 4)], external_arcs);
+        let names: Vec<_> = names.iter().map(|n| n.to_string()).collect();
+        HeuristicImportResolver::resolve_offsets(&mut reader, &offsets, FPackageId::from_name("/Game/Self"), &names, 4)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn heuristic_name_map() -> crate::GenericResult<()> {
+        let names = ["/Game/Characters/Player", "/Script/Engine", "!/Game/Ünïcode", "None"];
+        let read = HeuristicImportResolver::read_name_map(&mut name_batch(&names))?;
+        assert_eq!(read, ["/Game/Characters/Player", "/Script/Engine", "/Game/Ünïcode", "None"]);
+        assert!(HeuristicImportResolver::read_name_map(&mut name_batch(&[]))?.is_empty());
+        // Cut off part way through the strings
+        let mut reader = name_batch(&names);
+        reader.get_mut().truncate(reader.get_ref().len() - 2);
+        assert!(HeuristicImportResolver::read_name_map(&mut reader).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn heuristic_graph_data() -> crate::GenericResult<()> {
+        let (mut reader, offsets) = graph_data(&[], &[(0, 2), (2, 4)], &[1, 0, 3]);
+        assert_eq!(HeuristicImportResolver::read_graph_data(&mut reader, &offsets, 6)?, (2, 3));
+        // The second bundle should start at entry 2
+        let (mut reader, offsets) = graph_data(&[], &[(0, 2), (0, 4)], &[]);
+        assert!(HeuristicImportResolver::read_graph_data(&mut reader, &offsets, 6).is_err());
+        // Arcs that run past the end of the package header
+        let (mut reader, mut offsets) = graph_data(&[], &[(0, 2)], &[2]);
+        offsets.header_end -= 1;
+        assert!(HeuristicImportResolver::read_graph_data(&mut reader, &offsets, 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn heuristic_import_map() -> crate::GenericResult<()> {
+        // Script imports (type 1) and null (all bits set) don't refer to imported packages
+        let (mut reader, offsets) = graph_data(&[package_import(0), 1 << 62, package_import(2) | 5, u64::MAX], &[], &[]);
+        assert_eq!(HeuristicImportResolver::read_import_map(&mut reader, &offsets)?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn heuristic_import_order() {
+        let names = ["/Game/Zed", "/Script/CoreUObject", "/Game/Self", "/Game/Alpha", "/Game/Alpha.Alpha_C", "Default__Alpha_C"];
+        let imports = resolve(&names, &[package_import(0), package_import(1)], &[1, 2]).unwrap();
+        // Imported packages are in package id order rather than name map order
+        let mut expected = ["/Game/Zed", "/Game/Alpha"].map(FPackageId::from_name);
+        expected.sort_by_key(|id| id.0);
+        assert_eq!(imports.imported_packages, expected);
+        assert_eq!(imports.package_names.iter().map(|n| FPackageId::from_name(n)).collect::<Vec<_>>(), expected);
+        assert_eq!(imports.export_bundle_count, 1);
+        assert_eq!(imports.confidence, ImportConfidence::Probable);
+
+        let imports = resolve(&names[..3], &[package_import(0)], &[1]).unwrap();
+        assert_eq!(imports.package_names, ["/Game/Zed"]);
+        assert_eq!(imports.confidence, ImportConfidence::Exact);
+        // Without any imports, every package name is a soft reference
+        let imports = resolve(&names, &[], &[]).unwrap();
+        assert!(imports.imported_packages.is_empty());
+        assert_eq!(imports.confidence, ImportConfidence::Exact);
+    }
+
+    #[test]
+    fn heuristic_import_count_mismatch() {
+        let names = ["/Game/Zed", "/Game/Alpha"];
+        assert!(resolve(&names, &[package_import(0)], &[1, 1, 1]).unwrap_err().contains("imports 3 packages"));
+        assert!(resolve(&names, &[package_import(0)], &[1]).unwrap_err().contains("imports 1 packages"));
+        // The import map refers to a package past the end of the graph data
+        assert!(resolve(&names, &[package_import(2)], &[1, 1]).unwrap_err().contains("imported package 2"));
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use anyhow::anyhow;
+use retoc::container_header::StoreEntry;
+use retoc::{lower_utf16_cityhash, FPackageId};
+use retoc::ser::{ReadExt, WriteExt};
+use serde::{Deserialize, Serialize};
+use crate::GenericResult;
+use crate::metadata::UtocMetadata;
+
+/// A human editable form of asset metadata, for rendering as JSON or TOML.
+/// Packages are written as their package name (e.g /Game/Characters/Player) when it's known, otherwise
+/// as their package id in hex (e.g 0x0123456789ABCDEF). Either form can be used when editing
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MetadataDocument {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub validated_imports: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub manual_imports: Vec<ManualImportText>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub store_entries: Vec<StoreEntryText>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManualImportText {
+    pub package: String,
+    pub imports: Vec<String>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreEntryText {
+    pub package: String,
+    pub export_bundles_size: u64,
+    pub export_count: i32,
+    pub export_bundle_count: i32,
+    pub imported_packages: Vec<String>,
+    /// Every other field in the store entry (e.g shader map hashes), serialized and written in hex.
+    /// The fields above are applied on top of this when the document is imported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other: Option<String>
+}
+
+/// Package ids that can be turned back into their package name
+#[derive(Debug, Default)]
+pub struct PackageNames(HashMap<FPackageId, String>);
+
+impl PackageNames {
+    pub fn add(&mut self, name: &str) {
+        self.0.insert(FPackageId(lower_utf16_cityhash(name)), name.to_owned());
+    }
+
+    /// Only adds the name if it hashes to the expected id, for when the name had to be guessed from
+    /// the asset's path
+    pub fn add_checked(&mut self, name: &str, id: FPackageId) -> bool {
+        let matches = FPackageId(lower_utf16_cityhash(name)) == id;
+        if matches {
+            self.0.insert(id, name.to_owned());
+        }
+        matches
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn get(&self, id: FPackageId) -> String {
+        self.0.get(&id).map_or_else(|| format!("0x{:016X}", id.0), |v| v.clone())
+    }
+}
+
+pub fn parse_package(package: &str) -> GenericResult<FPackageId> {
+    let package = package.trim();
+    if let Some(hex) = package.strip_prefix("0x") {
+        return Ok(FPackageId(u64::from_str_radix(hex, 16)
+            .map_err(|_| anyhow!("\"{}\" is not a valid package id", package))?));
+    }
+    if !package.starts_with('/') {
+        return Err(anyhow!("\"{}\" must either be a package name starting with / or a package id starting with 0x", package).into_boxed_dyn_error());
+    }
+    Ok(FPackageId(lower_utf16_cityhash(package)))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> GenericResult<Vec<u8>> {
+    let hex = hex.trim();
+    if hex.len() % 2 != 0 {
+        return Err(anyhow!("Hex string has an odd number of digits").into_boxed_dyn_error());
+    }
+    Ok((0..hex.len() / 2).map(|i| u8::from_str_radix(&hex[i * 2..(i + 1) * 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?)
+}
+
+impl StoreEntryText {
+    fn new(package: FPackageId, entry: StoreEntry, names: &PackageNames) -> GenericResult<Self> {
+        let mut other = entry.clone();
+        other.export_bundles_size = 0;
+        other.export_count = 0;
+        other.export_bundle_count = 0;
+        other.imported_packages = vec![];
+        let other = match other == StoreEntry::default() {
+            true => None,
+            false => {
+                let mut data = Cursor::new(vec![]);
+                data.ser(&other)?;
+                Some(to_hex(data.get_ref()))
+            }
+        };
+        Ok(Self {
+            package: names.get(package),
+            export_bundles_size: entry.export_bundles_size,
+            export_count: entry.export_count,
+            export_bundle_count: entry.export_bundle_count,
+            imported_packages: entry.imported_packages.iter().map(|v| names.get(*v)).collect(),
+            other
+        })
+    }
+
+    fn to_store_entry(&self) -> GenericResult<StoreEntry> {
+        let mut entry: StoreEntry = match &self.other {
+            Some(other) => Cursor::new(from_hex(other)?).de()?,
+            None => StoreEntry::default()
+        };
+        entry.export_bundles_size = self.export_bundles_size;
+        entry.export_count = self.export_count;
+        entry.export_bundle_count = self.export_bundle_count;
+        entry.imported_packages = self.imported_packages.iter()
+            .map(|v| parse_package(v)).collect::<GenericResult<Vec<_>>>()?;
+        Ok(entry)
+    }
+}
+
+impl MetadataDocument {
+    pub fn from_metadata(metadata: UtocMetadata, names: &PackageNames) -> GenericResult<Self> {
+        let parts = metadata.into_parts();
+        Ok(Self {
+            validated_imports: parts.alt_import_assets.into_iter().map(|v| names.get(v)).collect(),
+            manual_imports: parts.manual_import_assets.into_iter().map(|(package, imports)| ManualImportText {
+                package: names.get(package),
+                imports: imports.into_iter().map(|v| names.get(v)).collect()
+            }).collect(),
+            store_entries: parts.store_entries.into_iter()
+                .map(|(package, entry)| StoreEntryText::new(package, entry, names))
+                .collect::<GenericResult<Vec<_>>>()?
+        })
+    }
+
+    pub fn to_metadata(&self) -> GenericResult<UtocMetadata> {
+        let mut metadata = UtocMetadata::default();
+        for package in &self.validated_imports {
+            metadata.add_graph_package_validated_entry_ue4(parse_package(package)?);
+        }
+        for manual in &self.manual_imports {
+            metadata.add_manual_v1_import(parse_package(&manual.package)?, manual.imports.iter()
+                .map(|v| parse_package(v)).collect::<GenericResult<Vec<_>>>()?);
+        }
+        for entry in &self.store_entries {
+            let store = entry.to_store_entry()
+                .map_err(|e| anyhow!("Store entry for {}: {}", entry.package, e))?;
+            metadata.add_from_store_entry(parse_package(&entry.package)?, store)?;
+        }
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use retoc::container_header::StoreEntry;
+    use retoc::ser::WriteExt;
+    use crate::GenericResult;
+    use crate::metadata::UtocMetadata;
+    use super::{from_hex, parse_package, to_hex, MetadataDocument, PackageNames, StoreEntryText};
+
+    fn metadata() -> GenericResult<UtocMetadata> {
+        let mut metadata = UtocMetadata::default();
+        metadata.add_graph_package_validated_entry_ue4(parse_package("/Game/Player")?);
+        metadata.add_manual_v1_import(parse_package("/Game/Enemy")?, vec![parse_package("/Game/Player")?]);
+        metadata.add_from_store_entry(parse_package("0x0123456789ABCDEF")?, StoreEntry {
+            export_bundles_size: 0x1000,
+            export_count: 2,
+            export_bundle_count: 1,
+            imported_packages: vec![parse_package("/Game/Enemy")?],
+            ..Default::default()
+        })?;
+        Ok(metadata)
+    }
+
+    #[test]
+    fn document_round_trip() -> GenericResult<()> {
+        let mut names = PackageNames::default();
+        names.add("/Game/Player");
+        names.add("/Game/Enemy");
+        let document = MetadataDocument::from_metadata(metadata()?, &names)?;
+        assert_eq!(document.validated_imports, vec!["/Game/Player".to_owned()]);
+        // Packages without a name are written as their id
+        assert_eq!(document.store_entries[0].package, "0x0123456789ABCDEF");
+        let json: MetadataDocument = serde_json::from_str(&serde_json::to_string_pretty(&document)?)?;
+        assert_eq!(json.to_metadata()?, metadata()?);
+        let toml: MetadataDocument = toml::from_str(&toml::to_string(&document)?)?;
+        assert_eq!(toml.to_metadata()?, metadata()?);
+        Ok(())
+    }
+
+    #[test]
+    fn store_entry_other_hex() -> GenericResult<()> {
+        let mut other = Cursor::new(vec![]);
+        other.ser(&StoreEntry::default())?;
+        let document = MetadataDocument {
+            store_entries: vec![StoreEntryText {
+                package: "/Game/Player".to_owned(),
+                export_bundles_size: 0x200,
+                export_count: 1,
+                export_bundle_count: 1,
+                imported_packages: vec![],
+                other: Some(to_hex(other.get_ref()))
+            }],
+            ..Default::default()
+        };
+        for text in [serde_json::to_string(&document)?, toml::to_string(&document)?] {
+            assert!(text.contains(&to_hex(other.get_ref())));
+        }
+        let toml: MetadataDocument = toml::from_str(&toml::to_string(&document)?)?;
+        let entry = toml.to_metadata()?.get_manual_v2_import(parse_package("/Game/Player")?).unwrap();
+        assert_eq!(entry, StoreEntry { export_bundles_size: 0x200, export_count: 1, export_bundle_count: 1, ..Default::default() });
+        assert_eq!(from_hex(&to_hex(&[0x00, 0xAB, 0x7F]))?, vec![0x00, 0xAB, 0x7F]);
+        assert!(from_hex("ABC").is_err());
+        assert!(from_hex("GG").is_err());
+        Ok(())
+    }
+}